@@ -1,148 +1,645 @@
-use super::llm_client::{LlmClient, Message, ToolCall};
+use super::context::{self, ContextManager};
+use super::environment;
+use super::events::{AgentEvent, Observer};
+use super::llm_client::{Completion, LlmClient, Message, StreamDelta, Tool, ToolCall};
+use super::permissions::{Decision, PermissionPolicy, Rule};
+use super::retry::{LlmError, RetryNotice};
+use super::session::{Session, SessionRecorder};
 use super::tool_registry::ToolRegistry;
-use crate::tools::ToolParams;
-use anyhow::{Context, Result};
-use colored::*;
-use serde_json;
+use super::usage::UsageTracker;
+use super::validation::{self, ParsedArguments};
+use crate::config::Config;
+use crate::tools::{AsyncTool, ToolContext, ToolParams, ToolResult};
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful coding assistant with access to file operations, \
+    search tools, and bash commands. Use these tools to help the user accomplish their tasks. \
+    Be concise and efficient in your tool usage.";
+
+/// Completion token limit for summarising compacted history
+const SUMMARY_MAX_TOKENS: u32 = 1024;
+
+/// Most read-only tool calls executed at the same time
+const MAX_PARALLEL_TOOLS: usize = 4;
+
+/// A tool call after validation and the permission check
+enum PreparedCall {
+    /// Not executed; the result explains why
+    Rejected(ToolResult),
+    Approved {
+        args: ParsedArguments,
+        read_only: bool,
+    },
+}
+
+/// Returned (wrapped in `anyhow::Error`) by `AgentLoop::run_cancellable`
+/// when the turn was cancelled; callers can `downcast_ref::<Interrupted>()`
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Returned (wrapped in `anyhow::Error`) when a turn needs more LLM
+/// requests than the iteration limit allows
+#[derive(Debug)]
+pub struct MaxIterationsExceeded(pub usize);
+
+impl std::fmt::Display for MaxIterationsExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Maximum iterations ({}) exceeded", self.0)
+    }
+}
+
+impl std::error::Error for MaxIterationsExceeded {}
+
+/// Context added to errors of LLM requests, so callers can tell them from
+/// other failures with `is::<LlmRequestFailed>()`
+#[derive(Debug)]
+pub struct LlmRequestFailed(pub &'static str);
+
+impl std::fmt::Display for LlmRequestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
 
 pub struct AgentLoop {
     client: LlmClient,
-    registry: ToolRegistry,
+    registry: Arc<ToolRegistry>,
+    /// Bounds how many tool calls run concurrently
+    tool_slots: Arc<Semaphore>,
     messages: Vec<Message>,
     max_iterations: usize,
-    verbose: bool,
+    /// Completion token limit for each LLM request
+    max_tokens: u32,
+    stream: bool,
+    usage: UsageTracker,
+    context: ContextManager,
+    session: Option<SessionRecorder>,
+    permissions: PermissionPolicy,
+    /// Cancels the current turn, including the tools it runs
+    cancel: CancellationToken,
+    /// Directory the tools work in
+    cwd: PathBuf,
+    /// Rules naming the only tools offered, while a custom command limits them
+    allowed_tools: Option<Vec<Rule>>,
+    /// Start the system prompt with a description of the environment
+    environment: bool,
+    observers: Vec<Mutex<Observer>>,
 }
 
 impl AgentLoop {
-    pub fn new(verbose: bool) -> Result<Self> {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let client = LlmClient::new(&config.llm)?;
+        let usage = UsageTracker::new(client.model());
+        let context = ContextManager::new(config.agent.context_window, config.agent.compact_threshold);
+
         Ok(Self {
-            client: LlmClient::new()?,
-            registry: ToolRegistry::new(),
+            client,
+            registry: Arc::new(ToolRegistry::load(config).await),
+            tool_slots: Arc::new(Semaphore::new(MAX_PARALLEL_TOOLS)),
             messages: Vec::new(),
-            max_iterations: 25,
-            verbose,
+            max_iterations: config.agent.max_iterations,
+            max_tokens: config.llm.max_tokens,
+            stream: false,
+            usage,
+            context,
+            session: None,
+            permissions: PermissionPolicy::from_config(config)?,
+            cancel: CancellationToken::new(),
+            cwd: std::env::current_dir().context("Failed to get current directory")?,
+            allowed_tools: None,
+            environment: config.agent.environment,
+            observers: Vec::new(),
         })
     }
 
+    /// Run tools in `cwd` instead of the process's working directory. Must
+    /// be called before the session is started.
+    pub fn set_working_dir(&mut self, cwd: PathBuf) {
+        self.permissions.set_cwd(cwd.clone());
+        self.cwd = cwd;
+    }
+
+    /// Report the progress of every turn to `observer`, in addition to the
+    /// observers added before. Observers run synchronously, in order.
+    pub fn on_event(&mut self, observer: impl FnMut(&AgentEvent) + Send + 'static) {
+        self.observers.push(Mutex::new(Box::new(observer)));
+    }
+
+    fn emit(&self, event: AgentEvent) {
+        for observer in &self.observers {
+            (observer.lock().unwrap())(&event);
+        }
+    }
+
+    fn emit_retry(&self, retry: RetryNotice) {
+        self.emit(AgentEvent::Retrying {
+            message: retry.to_string(),
+            reason: retry.reason,
+            attempt: retry.attempt,
+            max_attempts: retry.max_attempts,
+            delay_secs: retry.delay.as_secs_f64(),
+        });
+    }
+
+    /// The policy tool calls are checked against
+    pub fn permissions_mut(&mut self) -> &mut PermissionPolicy {
+        &mut self.permissions
+    }
+
+    /// Whether tool calls needing approval may prompt the user; when false
+    /// they are denied and the denial is reported to the model
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.permissions.set_interactive(interactive);
+    }
+
+    /// Start recording the conversation to a new session file
+    pub fn start_session(&mut self) -> Result<()> {
+        let mut recorder = SessionRecorder::create(self.client.model(), &self.cwd)?;
+        recorder.sync(&self.messages)?;
+        self.session = Some(recorder);
+        Ok(())
+    }
+
+    /// Restore a saved conversation and keep appending to its file
+    pub fn resume_session(&mut self, session: Session) -> Result<()> {
+        self.session = Some(SessionRecorder::resume(&session)?);
+        self.messages = session.messages;
+        Ok(())
+    }
+
+    pub fn session_id(&self) -> Option<&str> {
+        self.session.as_ref().map(|s| s.id())
+    }
+
+    pub fn model(&self) -> &str {
+        self.client.model()
+    }
+
+    /// Send the rest of the conversation to `model`, compacting it at
+    /// `context_window` tokens
+    pub fn set_model(&mut self, model: String, context_window: usize) {
+        self.usage.set_model(&model);
+        self.client.set_model(model);
+        self.context.context_window = context_window;
+    }
+
+    /// Tools offered to the model
+    pub fn tools(&self) -> impl Iterator<Item = &dyn AsyncTool> {
+        self.registry.tools().filter(|tool| self.offers(tool.name()))
+    }
+
+    fn offers(&self, tool: &str) -> bool {
+        self.allowed_tools
+            .as_ref()
+            .is_none_or(|rules| rules.iter().any(|rule| rule.names(tool)))
+    }
+
+    /// Offer only the tools named by permission `rules` such as
+    /// `bash(git diff*)`, and allow calls matching them without asking.
+    /// Deny and ask rules still apply. `None` lifts the restriction.
+    pub fn restrict_tools(&mut self, rules: Option<&[String]>) -> Result<()> {
+        let Some(rules) = rules else {
+            self.permissions.set_granted(Vec::new());
+            self.allowed_tools = None;
+            return Ok(());
+        };
+
+        let rules = rules.iter().map(|rule| Rule::parse(rule)).collect::<Result<Vec<_>>>()?;
+        for rule in &rules {
+            if !self.registry.tools().any(|tool| rule.names(tool.name())) {
+                bail!("No tool is named by '{}'", rule);
+            }
+        }
+        self.permissions.set_granted(rules.clone());
+        self.allowed_tools = Some(rules);
+        Ok(())
+    }
+
+    /// Receive responses token by token, reported as delta events
+    pub fn set_streaming(&mut self, stream: bool) {
+        self.stream = stream;
+    }
+
+    /// Start the conversation with `system_prompt`, preceded by the
+    /// environment section unless `agent.environment` is off. Call after
+    /// `set_working_dir`.
     pub fn set_system_prompt(&mut self, system_prompt: String) {
+        let content = if self.environment {
+            format!("{}\n\n{}", environment::describe(&self.cwd), system_prompt)
+        } else {
+            system_prompt
+        };
         self.messages.push(Message {
             role: "system".to_string(),
-            content: system_prompt,
+            content,
             tool_calls: None,
             tool_call_id: None,
+            is_error: false,
+        });
+    }
+
+    /// The system prompt the conversation started with, if it has one
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.messages
+            .first()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+    }
+
+    /// Use `system_prompt` as is from the next request on
+    pub fn replace_system_prompt(&mut self, system_prompt: String) {
+        match self.messages.first_mut().filter(|m| m.role == "system") {
+            Some(message) => message.content = system_prompt,
+            None => self.messages.insert(
+                0,
+                Message {
+                    role: "system".to_string(),
+                    content: system_prompt,
+                    tool_calls: None,
+                    tool_call_id: None,
+                    is_error: false,
+                },
+            ),
+        }
+        self.persist_replace();
+    }
+
+    /// Run a turn with `user_prompt` and return the final answer. Stops
+    /// early when `cancel` fires: the in-flight LLM request is abandoned and
+    /// running tools are cancelled; every tool call made so far still gets
+    /// a result, so the history stays valid.
+    /// A turn cancelled before the model answered is removed entirely.
+    /// Fails with `Interrupted` in that case.
+    pub async fn run_cancellable(
+        &mut self,
+        user_prompt: String,
+        cancel: CancellationToken,
+    ) -> Result<String> {
+        self.emit(AgentEvent::TurnStarted {
+            prompt: user_prompt.clone(),
+        });
+        let result = self.run_turn(user_prompt, cancel).await;
+
+        let (tokens, requests) = self.usage.turn();
+        self.emit(AgentEvent::Usage {
+            tokens,
+            requests,
+            cost_usd: self.usage.turn_cost(),
+            summary: self.usage.turn_summary(),
+        });
+        self.emit(match &result {
+            Ok(answer) => AgentEvent::Finished {
+                answer: answer.clone(),
+            },
+            Err(e) if e.is::<Interrupted>() => AgentEvent::Interrupted,
+            Err(e) => AgentEvent::Error {
+                message: format!("{:#}", e),
+            },
         });
+        result
     }
 
-    pub async fn run(&mut self, user_prompt: String) -> Result<String> {
+    async fn run_turn(&mut self, user_prompt: String, cancel: CancellationToken) -> Result<String> {
+        self.cancel = cancel.clone();
+
         // Add user message
         self.messages.push(Message {
             role: "user".to_string(),
             content: user_prompt.clone(),
             tool_calls: None,
             tool_call_id: None,
+            is_error: false,
         });
+        self.persist();
 
-        if self.verbose {
-            println!("{} {}", "User:".bright_cyan().bold(), user_prompt);
-            println!();
-        }
+        self.usage.start_turn();
 
         let mut iteration = 0;
-        let tool_definitions = self.registry.get_tool_definitions();
+        // Whether the model answered at least once during this turn
+        let mut responded = false;
+        let tool_definitions: Vec<Tool> = self
+            .registry
+            .get_tool_definitions()
+            .into_iter()
+            .filter(|tool| self.offers(&tool.function.name))
+            .collect();
 
         loop {
             iteration += 1;
             if iteration > self.max_iterations {
-                anyhow::bail!("Maximum iterations ({}) exceeded", self.max_iterations);
+                return Err(MaxIterationsExceeded(self.max_iterations).into());
             }
 
-            if self.verbose {
-                println!(
-                    "{} {}",
-                    "Iteration:".dimmed(),
-                    format!("{}/{}", iteration, self.max_iterations).dimmed()
-                );
+            self.emit(AgentEvent::Iteration {
+                number: iteration,
+                max: self.max_iterations,
+            });
+
+            if self
+                .context
+                .needs_compaction(&self.messages, &tool_definitions, self.max_tokens)
+            {
+                let Some(compacted) = cancel.run_until_cancelled(self.compact()).await else {
+                    return Err(self.interrupt(responded));
+                };
+                compacted?;
             }
 
             // Call LLM
-            let response = self
-                .client
-                .chat(self.messages.clone(), Some(tool_definitions.clone()), Some(4096))
-                .await
-                .context("Failed to get LLM response")?;
+            let Some(completion) = cancel.run_until_cancelled(self.complete(&tool_definitions)).await
+            else {
+                return Err(self.interrupt(responded));
+            };
+            let completion = match completion {
+                Ok(completion) => completion,
+                Err(error) if is_context_length_error(&error) => {
+                    // The local estimate was too optimistic; compact and try once more
+                    let retried = cancel
+                        .run_until_cancelled(async {
+                            if !self.compact().await? {
+                                return Err(error);
+                            }
+                            self.complete(&tool_definitions).await
+                        })
+                        .await;
+                    match retried {
+                        Some(completion) => completion?,
+                        None => return Err(self.interrupt(responded)),
+                    }
+                }
+                Err(error) => return Err(error),
+            };
+
+            self.usage.record(completion.usage);
+            let response = completion.message;
+            if !response.content.is_empty() {
+                self.emit(AgentEvent::Text {
+                    text: response.content.clone(),
+                });
+            }
 
             // Check if LLM wants to use tools
             if let Some(tool_calls) = &response.tool_calls {
-                if self.verbose {
-                    println!(
-                        "{} {} tool call(s)",
-                        "Assistant:".bright_green().bold(),
-                        tool_calls.len()
-                    );
-                }
+                self.emit(AgentEvent::ToolCallsRequested {
+                    count: tool_calls.len(),
+                });
 
                 // Add assistant's message with tool calls to history
                 self.messages.push(response.clone());
+                self.persist();
+                responded = true;
 
-                // Execute each tool call
-                for tool_call in tool_calls {
-                    self.execute_tool_call(tool_call).await?;
+                self.execute_tool_calls(tool_calls).await;
+                if cancel.is_cancelled() {
+                    return Err(self.interrupt(responded));
                 }
             } else {
-                // No more tool calls, return final response
-                if self.verbose {
-                    println!("{}", "Assistant:".bright_green().bold());
-                    println!("{}", response.content);
-                    println!();
-                }
-
-                // Add final response to history
+                // No more tool calls; add final response to history
                 self.messages.push(response.clone());
+                self.persist();
 
                 return Ok(response.content);
             }
         }
     }
 
-    async fn execute_tool_call(&mut self, tool_call: &ToolCall) -> Result<()> {
+    /// Leave the history of a cancelled turn in a state the model accepts.
+    /// Tool rounds that already happened are kept, since their effects are
+    /// real; a prompt that was never answered is dropped.
+    fn interrupt(&mut self, responded: bool) -> anyhow::Error {
+        if !responded && self.messages.last().is_some_and(|m| m.role == "user") {
+            self.messages.pop();
+            self.persist_replace();
+        }
+        Interrupted.into()
+    }
+
+    async fn complete(&self, tool_definitions: &[Tool]) -> Result<Completion> {
+        if self.stream {
+            self.stream_response(tool_definitions).await
+        } else {
+            self.client
+                .chat(
+                    &self.messages,
+                    Some(tool_definitions),
+                    Some(self.context.output_budget(self.max_tokens)),
+                    |retry| self.emit_retry(retry),
+                )
+                .await
+        }
+        .context(LlmRequestFailed("Failed to get LLM response"))
+    }
+
+    /// Summarise older turns into a system note to free up context.
+    ///
+    /// Returns false if there was nothing that could be compacted.
+    pub async fn compact(&mut self) -> Result<bool> {
+        let Some(plan) = self.context.plan(&self.messages) else {
+            return Ok(false);
+        };
+
+        let before = context::estimate_tokens(&self.messages);
+        let summarized = plan.summarized.len();
+
+        let completion = self
+            .client
+            .chat(&plan.summary_request(), None, Some(SUMMARY_MAX_TOKENS), |retry| {
+                self.emit_retry(retry)
+            })
+            .await
+            .context(LlmRequestFailed("Failed to summarise conversation history"))?;
+        self.usage.record(completion.usage);
+
+        self.messages = plan.apply(&completion.message.content);
+        self.persist_replace();
+
+        self.emit(AgentEvent::Compacted {
+            messages: summarized,
+            tokens_before: before,
+            tokens_after: context::estimate_tokens(&self.messages),
+        });
+
+        Ok(true)
+    }
+
+    async fn stream_response(&self, tool_definitions: &[Tool]) -> Result<Completion> {
+        self.client
+            .chat_stream(
+                &self.messages,
+                Some(tool_definitions),
+                Some(self.context.output_budget(self.max_tokens)),
+                |delta| {
+                    self.emit(match delta {
+                        StreamDelta::Text(text) => AgentEvent::TextDelta { text },
+                        StreamDelta::ToolCallStarted { index, name } => {
+                            AgentEvent::ToolCallStarted { index, name }
+                        }
+                        StreamDelta::ToolCallArguments { index, arguments } => {
+                            AgentEvent::ToolArgumentsDelta { index, arguments }
+                        }
+                    })
+                },
+                |retry| self.emit_retry(retry),
+            )
+            .await
+    }
+
+    /// Run the tool calls of one assistant message and append their results
+    /// in call order. Consecutive read-only calls run concurrently; any other
+    /// call waits for everything before it and runs on its own.
+    async fn execute_tool_calls(&mut self, tool_calls: &[ToolCall]) {
+        // Validate and ask for permission up front, in call order, so
+        // prompts appear in the order the model made the calls
+        let mut prepared = Vec::new();
+        for tool_call in tool_calls {
+            prepared.push(self.prepare_tool_call(tool_call).await);
+        }
+
+        let runs_alone =
+            |call: &PreparedCall| matches!(call, PreparedCall::Approved { read_only: false, .. });
+        let mut pending = tool_calls.iter().zip(prepared).peekable();
+
+        while let Some(mut first) = pending.next() {
+            // Calls still waiting when the turn is cancelled are not started
+            if self.cancel.is_cancelled() {
+                first.1 = PreparedCall::Rejected(cancelled());
+            }
+            let mut batch = vec![first];
+            if !runs_alone(&batch[0].1) {
+                while let Some(call) = pending.next_if(|(_, call)| !runs_alone(call)) {
+                    batch.push(call);
+                }
+            }
+
+            // Start every call in the batch before waiting for any of them
+            let running: Vec<_> = batch
+                .into_iter()
+                .map(|(tool_call, call)| match call {
+                    PreparedCall::Approved { args, .. } => {
+                        (tool_call, Err(self.spawn_tool(&tool_call.function.name, args)))
+                    }
+                    PreparedCall::Rejected(result) => (tool_call, Ok(result)),
+                })
+                .collect();
+
+            for (tool_call, outcome) in running {
+                let result = match outcome {
+                    Ok(result) => result,
+                    Err(handle) => handle
+                        .await
+                        .unwrap_or_else(|e| ToolResult::error(format!("Tool failed: {}", e))),
+                };
+                self.record_tool_result(tool_call, result);
+                self.persist();
+            }
+        }
+    }
+
+    /// Report, validate and permission-check a tool call
+    async fn prepare_tool_call(&mut self, tool_call: &ToolCall) -> PreparedCall {
         let tool_name = &tool_call.function.name;
         let arguments = &tool_call.function.arguments;
 
-        if self.verbose {
-            println!(
-                "  {} {}",
-                "→".bright_yellow(),
-                format!("{}({})", tool_name, arguments).dimmed()
-            );
+        self.emit(AgentEvent::ToolCall {
+            id: tool_call.id.clone(),
+            name: tool_name.clone(),
+            arguments: arguments.clone(),
+        });
+
+        if self.cancel.is_cancelled() {
+            return PreparedCall::Rejected(cancelled());
+        }
+
+        // Problems with the call itself go back to the model as a tool error
+        // so it can correct the call instead of ending the run
+        let args = match self.check_tool_call(tool_name, arguments) {
+            Ok(args) => args,
+            Err(message) => return PreparedCall::Rejected(ToolResult::error(message)),
+        };
+        match self.permissions.check(tool_name, &args.value, &self.cancel).await {
+            Decision::Allow => PreparedCall::Approved {
+                read_only: self.registry.get(tool_name).is_some_and(|tool| tool.is_read_only()),
+                args,
+            },
+            Decision::Deny(_) if self.cancel.is_cancelled() => PreparedCall::Rejected(cancelled()),
+            Decision::Deny(reason) => {
+                self.emit(AgentEvent::PermissionDenied {
+                    id: tool_call.id.clone(),
+                    name: tool_name.clone(),
+                    reason: reason.clone(),
+                });
+                PreparedCall::Rejected(ToolResult::error(reason))
+            }
         }
+    }
+
+    /// Execute a tool once a slot is free
+    fn spawn_tool(&self, tool_name: &str, args: ParsedArguments) -> tokio::task::JoinHandle<ToolResult> {
+        let registry = Arc::clone(&self.registry);
+        let slots = Arc::clone(&self.tool_slots);
+        let tool_name = tool_name.to_string();
+        let context = self.tool_context();
 
-        // Parse arguments as JSON
-        let args: serde_json::Value = serde_json::from_str(arguments)
-            .context(format!("Failed to parse tool arguments: {}", arguments))?;
+        tokio::spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let params = ToolParams { data: args.value.clone() };
+            let mut result = registry
+                .execute_tool(&tool_name, params, &context)
+                .await
+                .unwrap_or_else(|e| ToolResult::error(format!("{:#}", e)));
+
+            if args.repaired {
+                let note = format!(
+                    "\n[Note: the arguments were not valid JSON and were repaired to {}]",
+                    args.value
+                );
+                match result.error.as_mut() {
+                    Some(error) => error.push_str(&note),
+                    None => result.output.push_str(&note),
+                }
+            }
+            result
+        })
+    }
 
-        let params = ToolParams { data: args };
+    fn tool_context(&self) -> ToolContext {
+        ToolContext {
+            cwd: self.cwd.clone(),
+            session_id: self.session_id().map(str::to_string),
+            cancel: self.cancel.clone(),
+            ..ToolContext::default()
+        }
+    }
 
-        // Execute the tool
-        let result = self.registry.execute_tool(tool_name, params)?;
+    fn record_tool_result(&mut self, tool_call: &ToolCall, result: ToolResult) {
+        self.emit(AgentEvent::ToolResult {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            success: result.success,
+            output: if result.success {
+                result.output.clone()
+            } else {
+                result.error.clone().unwrap_or_else(|| "Unknown error".to_string())
+            },
+        });
 
         // Format result message
+        let failed = !result.success;
         let result_content = if result.success {
-            if self.verbose {
-                println!(
-                    "  {} {}",
-                    "✓".bright_green(),
-                    result.output.lines().next().unwrap_or("Success")
-                );
-            }
             result.output
         } else {
-            if self.verbose {
-                println!(
-                    "  {} {}",
-                    "✗".bright_red(),
-                    result.error.as_ref().unwrap_or(&"Unknown error".to_string())
-                );
-            }
             format!("Error: {}", result.error.unwrap_or_else(|| "Unknown error".to_string()))
         };
 
@@ -152,16 +649,92 @@ impl AgentLoop {
             content: result_content,
             tool_calls: None,
             tool_call_id: Some(tool_call.id.clone()),
+            is_error: failed,
         });
+    }
 
-        Ok(())
+    /// Resolve the tool and parse and validate the arguments against its
+    /// schema, describing any problem in a way the model can act on
+    fn check_tool_call(&self, tool_name: &str, arguments: &str) -> Result<ParsedArguments, String> {
+        let Some(tool) = self.registry.get(tool_name).filter(|_| self.offers(tool_name)) else {
+            let available: Vec<&str> = self.tools().map(|tool| tool.name()).collect();
+            return Err(format!(
+                "Unknown tool '{}'. Available tools: {}",
+                tool_name,
+                available.join(", ")
+            ));
+        };
+
+        let args = validation::parse_arguments(arguments)
+            .map_err(|e| format!("Invalid arguments for '{}': {}", tool_name, e))?;
+        // A repair may have closed up a call cut off mid-`content`; running
+        // it would write a truncated file
+        if args.repaired && !tool.is_read_only() {
+            return Err(format!(
+                "Invalid arguments for '{}': not valid JSON, possibly cut off. Repaired, \
+                 they would be {}, but '{}' was not run because it makes changes. \
+                 Send the complete call again.",
+                tool_name, args.value, tool_name
+            ));
+        }
+
+        let problems = validation::validate(&tool.parameters_schema(), &args.value);
+        if !problems.is_empty() {
+            return Err(format!(
+                "Invalid arguments for '{}':\n- {}\nExpected: {}",
+                tool_name,
+                problems.join("\n- "),
+                validation::signature(tool_name, &tool.parameters_schema())
+            ));
+        }
+
+        Ok(args)
+    }
+
+    /// Token usage and estimated cost so far
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
     }
 
     pub fn get_conversation_history(&self) -> &[Message] {
         &self.messages
     }
 
+    /// Drop the conversation, keeping the system prompt
     pub fn clear_history(&mut self) {
-        self.messages.clear();
+        let keep = usize::from(self.system_prompt().is_some());
+        self.messages.truncate(keep);
+        self.persist_replace();
+    }
+
+    /// Append new messages to the session file. Failing to save must not
+    /// abort the conversation, so errors are only reported.
+    fn persist(&mut self) {
+        if let Some(Err(e)) = self.session.as_mut().map(|session| session.sync(&self.messages)) {
+            self.warn_unsaved(e);
+        }
     }
+
+    fn persist_replace(&mut self) {
+        if let Some(Err(e)) = self.session.as_mut().map(|session| session.replace(&self.messages)) {
+            self.warn_unsaved(e);
+        }
+    }
+
+    fn warn_unsaved(&self, error: anyhow::Error) {
+        self.emit(AgentEvent::Warning {
+            message: format!("Failed to save session: {:#}", error),
+        });
+    }
+}
+
+fn is_context_length_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<LlmError>(),
+        Some(LlmError::ContextLengthExceeded(_))
+    )
+}
+
+fn cancelled() -> ToolResult {
+    ToolResult::error("Cancelled by the user before it ran".to_string())
 }
@@ -0,0 +1,511 @@
+use super::{Backend, StreamDecoder};
+use crate::agent::llm_client::{
+    ChatRequest, Completion, FunctionCall, Message, StreamDelta, ToolCall, Usage,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires `max_tokens`; used when the caller does not set one
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Native Anthropic Messages API (`/v1/messages`)
+pub struct AnthropicBackend;
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        // Anthropic reports cached input separately from `input_tokens`
+        Usage {
+            prompt_tokens: usage.input_tokens
+                + usage.cache_read_input_tokens
+                + usage.cache_creation_input_tokens,
+            completion_tokens: usage.output_tokens,
+            cached_tokens: usage.cache_read_input_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        #[serde(default)]
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Error {
+        error: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl Backend for AnthropicBackend {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn endpoint(&self, base_url: &str) -> String {
+        let base_url = base_url.trim_end_matches('/');
+        if base_url.ends_with("/v1") {
+            format!("{}/messages", base_url)
+        } else {
+            format!("{}/v1/messages", base_url)
+        }
+    }
+
+    fn headers(&self, api_key: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("x-api-key", api_key.to_string()),
+            ("anthropic-version", ANTHROPIC_VERSION.to_string()),
+        ]
+    }
+
+    fn build_request(&self, request: &ChatRequest<'_>) -> Result<Value> {
+        let mut system = Vec::new();
+        let mut messages: Vec<Value> = Vec::new();
+
+        for message in request.messages {
+            let (role, blocks) = match message.role.as_str() {
+                "system" => {
+                    system.push(message.content.clone());
+                    continue;
+                }
+                "tool" => {
+                    let mut result = json!({
+                        "type": "tool_result",
+                        "tool_use_id": message.tool_call_id.clone().unwrap_or_default(),
+                        "content": message.content,
+                    });
+                    if message.is_error {
+                        result["is_error"] = json!(true);
+                    }
+                    ("user", vec![result])
+                }
+                "assistant" => {
+                    let mut blocks = Vec::new();
+                    if !message.content.is_empty() {
+                        blocks.push(json!({"type": "text", "text": message.content}));
+                    }
+                    for call in message.tool_calls.iter().flatten() {
+                        // The API rejects anything but an object here, so fall back
+                        // to an empty input if the model produced invalid JSON
+                        let input = serde_json::from_str::<Value>(&call.function.arguments)
+                            .ok()
+                            .filter(Value::is_object)
+                            .unwrap_or_else(|| json!({}));
+                        blocks.push(json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.function.name,
+                            "input": input,
+                        }));
+                    }
+                    // Empty content is rejected; an empty answer adds nothing
+                    if blocks.is_empty() {
+                        continue;
+                    }
+                    ("assistant", blocks)
+                }
+                _ => ("user", vec![json!({"type": "text", "text": message.content})]),
+            };
+
+            // Roles must alternate, so consecutive messages from the same side
+            // (e.g. several tool results) are merged into one
+            match messages.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(content) = last["content"].as_array_mut() {
+                        content.extend(blocks);
+                    }
+                }
+                _ => messages.push(json!({"role": role, "content": blocks})),
+            }
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        });
+
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        // Round-trip through the decimal form so 0.7f32 is not sent as 0.699999988
+        if let Some(temperature) = request.temperature {
+            let temperature: f64 = temperature.to_string().parse()?;
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            let top_p: f64 = top_p.to_string().parse()?;
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(tools) = request.tools {
+            body["tools"] = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.function.name,
+                        "description": tool.function.description,
+                        "input_schema": tool.function.parameters,
+                    })
+                })
+                .collect();
+        }
+        if request.stream {
+            body["stream"] = json!(true);
+        }
+
+        Ok(body)
+    }
+
+    fn parse_response(&self, body: Value) -> Result<Completion> {
+        let response: MessagesResponse =
+            serde_json::from_value(body).context("Failed to parse LLM response")?;
+
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+
+        for block in response.content {
+            match block {
+                ContentBlock::Text { text: t } => text.push_str(&t),
+                ContentBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(tool_call(id, name, input.to_string()));
+                }
+                ContentBlock::Other => {}
+            }
+        }
+
+        Ok(Completion {
+            message: assistant_message(text, tool_calls),
+            usage: response.usage.map(Usage::from),
+        })
+    }
+
+    fn stream_decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(AnthropicStreamDecoder::default())
+    }
+}
+
+/// Tracks content blocks as they are streamed
+#[derive(Debug, Default)]
+struct AnthropicStreamDecoder {
+    text: String,
+    tool_calls: Vec<ToolCall>,
+    /// Content block index of each entry in `tool_calls`
+    tool_blocks: Vec<usize>,
+    usage: Option<Usage>,
+}
+
+impl StreamDecoder for AnthropicStreamDecoder {
+    fn decode(&mut self, data: &str, on_delta: &mut dyn FnMut(StreamDelta)) -> Result<bool> {
+        let event: StreamEvent = serde_json::from_str(data)
+            .context(format!("Failed to parse stream event: {}", data))?;
+
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.usage = message.usage.map(Usage::from);
+            }
+            StreamEvent::MessageDelta { usage: Some(delta) } => {
+                // The final delta carries the cumulative output token count
+                self.usage.get_or_insert_with(Usage::default).completion_tokens =
+                    delta.output_tokens;
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name, .. },
+            } => {
+                self.tool_blocks.push(index);
+                self.tool_calls.push(tool_call(id, name.clone(), String::new()));
+                on_delta(StreamDelta::ToolCallStarted {
+                    index: self.tool_calls.len() - 1,
+                    name,
+                });
+            }
+            StreamEvent::ContentBlockDelta {
+                delta: BlockDelta::TextDelta { text },
+                ..
+            } => {
+                self.text.push_str(&text);
+                on_delta(StreamDelta::Text(text));
+            }
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: BlockDelta::InputJsonDelta { partial_json },
+            } => {
+                if let Some(position) = self.tool_blocks.iter().position(|&i| i == index) {
+                    self.tool_calls[position]
+                        .function
+                        .arguments
+                        .push_str(&partial_json);
+                    on_delta(StreamDelta::ToolCallArguments {
+                        index: position,
+                        arguments: partial_json,
+                    });
+                }
+            }
+            StreamEvent::MessageStop => return Ok(true),
+            StreamEvent::Error { error } => {
+                anyhow::bail!("LLM stream returned an error: {}", error);
+            }
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    fn finish(self: Box<Self>) -> Completion {
+        let mut tool_calls = self.tool_calls;
+        // Tools without parameters stream no input deltas at all
+        for call in &mut tool_calls {
+            if call.function.arguments.is_empty() {
+                call.function.arguments = "{}".to_string();
+            }
+        }
+        Completion {
+            message: assistant_message(self.text, tool_calls),
+            usage: self.usage,
+        }
+    }
+}
+
+fn tool_call(id: String, name: String, arguments: String) -> ToolCall {
+    ToolCall {
+        id,
+        tool_type: "function".to_string(),
+        function: FunctionCall { name, arguments },
+    }
+}
+
+fn assistant_message(content: String, tool_calls: Vec<ToolCall>) -> Message {
+    Message {
+        role: "assistant".to_string(),
+        content,
+        tool_calls: if tool_calls.is_empty() {
+            None
+        } else {
+            Some(tool_calls)
+        },
+        tool_call_id: None,
+        is_error: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::llm_client::{FunctionDefinition, Tool};
+    use crate::agent::sse::SseParser;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            is_error: false,
+        }
+    }
+
+    fn tool_result(id: &str, content: &str, is_error: bool) -> Message {
+        Message {
+            tool_call_id: Some(id.to_string()),
+            is_error,
+            ..message("tool", content)
+        }
+    }
+
+    #[test]
+    fn requests_map_to_alternating_content_blocks() {
+        let messages = [
+            message("system", "Be helpful"),
+            message("user", "Check both files"),
+            Message {
+                tool_calls: Some(vec![
+                    tool_call("call_a".to_string(), "read".to_string(), r#"{"file_path":"a.rs"}"#.to_string()),
+                    tool_call("call_b".to_string(), "read".to_string(), "{\"file_pa".to_string()),
+                ]),
+                ..message("assistant", "Reading them.")
+            },
+            tool_result("call_a", "fn main() {}", false),
+            tool_result("call_b", "Error: Invalid JSON", true),
+            message("assistant", ""),
+            message("user", "Go on"),
+        ];
+        let tools = [Tool {
+            tool_type: "function".to_string(),
+            function: FunctionDefinition {
+                name: "read".to_string(),
+                description: "Read a file".to_string(),
+                parameters: json!({"type": "object"}),
+            },
+        }];
+        let request = ChatRequest {
+            model: "claude-sonnet-4",
+            messages: &messages,
+            tools: Some(&tools),
+            tool_choice: None,
+            max_tokens: None,
+            temperature: Some(0.7),
+            top_p: None,
+            stream: true,
+        };
+
+        let body = AnthropicBackend.build_request(&request).unwrap();
+        assert_eq!(body["system"], "Be helpful");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["temperature"], 0.7);
+        assert_eq!(body["stream"], true);
+        assert_eq!(
+            body["tools"],
+            json!([{"name": "read", "description": "Read a file", "input_schema": {"type": "object"}}])
+        );
+        assert_eq!(
+            body["messages"],
+            json!([
+                {"role": "user", "content": [{"type": "text", "text": "Check both files"}]},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Reading them."},
+                    {"type": "tool_use", "id": "call_a", "name": "read", "input": {"file_path": "a.rs"}},
+                    {"type": "tool_use", "id": "call_b", "name": "read", "input": {}},
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "call_a", "content": "fn main() {}"},
+                    {"type": "tool_result", "tool_use_id": "call_b", "content": "Error: Invalid JSON", "is_error": true},
+                    {"type": "text", "text": "Go on"},
+                ]},
+            ])
+        );
+    }
+
+    #[test]
+    fn stream_assembles_text_and_tool_calls() {
+        let events = [
+            r#"{"type":"message_start","message":{"usage":{"input_tokens":10,"cache_read_input_tokens":8,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Let me "}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"look."}}"#,
+            r#"{"type":"ping"}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_a","name":"grep","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"pattern\":"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":" \"é\"}"}}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"tool_use","id":"toolu_b","name":"todo","input":{}}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":25}}"#,
+            r#"{"type":"message_stop"}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"ignored"}}"#,
+        ];
+        let body: String = events
+            .iter()
+            .map(|event| format!("event: x\ndata: {}\n\n", event))
+            .collect();
+
+        let mut parser = SseParser::new();
+        let mut decoder = AnthropicBackend.stream_decoder();
+        let mut deltas = Vec::new();
+        let mut on_delta = |delta| deltas.push(delta);
+        'stream: for piece in body.as_bytes().chunks(7) {
+            for event in parser.feed(piece) {
+                if decoder.decode(&event.data, &mut on_delta).unwrap() {
+                    break 'stream;
+                }
+            }
+        }
+
+        let completion = decoder.finish();
+        assert_eq!(completion.message.content, "Let me look.");
+        let calls = completion.message.tool_calls.unwrap();
+        let calls: Vec<_> = calls
+            .iter()
+            .map(|call| (call.id.as_str(), call.function.name.as_str(), call.function.arguments.as_str()))
+            .collect();
+        assert_eq!(
+            calls,
+            [("toolu_a", "grep", r#"{"pattern": "é"}"#), ("toolu_b", "todo", "{}")]
+        );
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.cached_tokens), (18, 25, 8));
+        assert!(matches!(&deltas[2], StreamDelta::ToolCallStarted { index: 0, name } if name == "grep"));
+        let arguments = deltas
+            .iter()
+            .filter(|delta| matches!(delta, StreamDelta::ToolCallArguments { index: 0, .. }))
+            .count();
+        assert_eq!(arguments, 2);
+    }
+
+    #[test]
+    fn stream_errors_fail_the_request() {
+        let mut decoder = AnthropicBackend.stream_decoder();
+        let error = decoder
+            .decode(r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#, &mut |_| {})
+            .unwrap_err();
+        assert!(error.to_string().contains("overloaded_error"));
+    }
+}
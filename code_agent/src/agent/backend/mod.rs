@@ -0,0 +1,54 @@
+pub mod anthropic;
+pub mod openai;
+
+use super::llm_client::{ChatRequest, Completion, StreamDelta};
+use anyhow::Result;
+
+pub use anthropic::AnthropicBackend;
+pub use openai::OpenAiBackend;
+
+/// Wire protocol spoken by an LLM endpoint
+///
+/// A backend only translates between the agent's OpenAI-shaped `Message`
+/// types and the provider's JSON; `LlmClient` owns the HTTP transport.
+pub trait Backend: Send + Sync {
+    /// Short identifier used in configuration and error messages
+    fn name(&self) -> &str;
+
+    /// Full URL of the chat endpoint for the given base URL
+    fn endpoint(&self, base_url: &str) -> String;
+
+    /// Authentication and protocol headers to send with every request
+    fn headers(&self, api_key: &str) -> Vec<(&'static str, String)>;
+
+    /// Build the JSON request body
+    fn build_request(&self, request: &ChatRequest<'_>) -> Result<serde_json::Value>;
+
+    /// Convert a non-streaming response body into an assistant message
+    fn parse_response(&self, body: serde_json::Value) -> Result<Completion>;
+
+    /// Create a decoder for a server-sent event stream
+    fn stream_decoder(&self) -> Box<dyn StreamDecoder>;
+}
+
+/// Incrementally decodes the `data:` payloads of a streaming response
+pub trait StreamDecoder: Send {
+    /// Apply one event payload, reporting deltas as they are decoded.
+    /// Returns true once the stream signals that it is complete.
+    fn decode(&mut self, data: &str, on_delta: &mut dyn FnMut(StreamDelta)) -> Result<bool>;
+
+    /// Assemble the final assistant message
+    fn finish(self: Box<Self>) -> Completion;
+}
+
+/// Look up a backend by its configured name
+pub fn from_name(name: &str) -> Result<Box<dyn Backend>> {
+    match name.to_lowercase().as_str() {
+        "openai" | "litellm" => Ok(Box::new(OpenAiBackend)),
+        "anthropic" => Ok(Box::new(AnthropicBackend)),
+        other => anyhow::bail!(
+            "Unknown LLM backend '{}'. Expected 'openai' or 'anthropic'",
+            other
+        ),
+    }
+}
@@ -0,0 +1,315 @@
+use super::{Backend, StreamDecoder};
+use crate::agent::llm_client::{
+    ChatRequest, Completion, FunctionCall, Message, StreamDelta, ToolCall, Usage,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// OpenAI-compatible `/chat/completions` API, as exposed by the LiteLLM proxy
+pub struct OpenAiBackend;
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+    #[serde(default)]
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: Option<u64>,
+}
+
+impl From<OpenAiUsage> for Usage {
+    fn from(usage: OpenAiUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cached_tokens: usage
+                .prompt_tokens_details
+                .and_then(|details| details.cached_tokens)
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionCallDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+impl Backend for OpenAiBackend {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn endpoint(&self, base_url: &str) -> String {
+        format!("{}/chat/completions", base_url.trim_end_matches('/'))
+    }
+
+    fn headers(&self, api_key: &str) -> Vec<(&'static str, String)> {
+        vec![("Authorization", format!("Bearer {}", api_key))]
+    }
+
+    fn build_request(&self, request: &ChatRequest<'_>) -> Result<serde_json::Value> {
+        let mut body = serde_json::to_value(request).context("Failed to serialize chat request")?;
+        // The API has no error flag on tool messages; failures are described
+        // in their content
+        for message in body["messages"].as_array_mut().into_iter().flatten() {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("is_error");
+            }
+        }
+        if request.stream {
+            // Ask for a final chunk carrying token usage
+            body["stream_options"] = json!({"include_usage": true});
+        }
+        Ok(body)
+    }
+
+    fn parse_response(&self, body: serde_json::Value) -> Result<Completion> {
+        let chat_response: ChatResponse =
+            serde_json::from_value(body).context("Failed to parse LLM response")?;
+
+        let message = chat_response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .context("No response from LLM")?;
+
+        Ok(Completion {
+            message,
+            usage: chat_response.usage.map(Usage::from),
+        })
+    }
+
+    fn stream_decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(OpenAiStreamDecoder::default())
+    }
+}
+
+/// Assembles streamed chunks into a complete assistant message
+#[derive(Debug, Default)]
+struct OpenAiStreamDecoder {
+    content: String,
+    tool_calls: BTreeMap<usize, ToolCall>,
+    usage: Option<Usage>,
+}
+
+impl OpenAiStreamDecoder {
+    fn apply(&mut self, delta: ChunkDelta, on_delta: &mut dyn FnMut(StreamDelta)) {
+        if let Some(text) = delta.content.filter(|t| !t.is_empty()) {
+            self.content.push_str(&text);
+            on_delta(StreamDelta::Text(text));
+        }
+
+        for call in delta.tool_calls.unwrap_or_default() {
+            let entry = self.tool_calls.entry(call.index).or_insert_with(|| ToolCall {
+                id: String::new(),
+                tool_type: "function".to_string(),
+                function: FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            });
+
+            if let Some(id) = call.id {
+                entry.id = id;
+            }
+
+            let Some(function) = call.function else {
+                continue;
+            };
+
+            if let Some(name) = function.name.filter(|n| !n.is_empty()) {
+                entry.function.name.push_str(&name);
+                on_delta(StreamDelta::ToolCallStarted {
+                    index: call.index,
+                    name: entry.function.name.clone(),
+                });
+            }
+
+            if let Some(arguments) = function.arguments.filter(|a| !a.is_empty()) {
+                entry.function.arguments.push_str(&arguments);
+                on_delta(StreamDelta::ToolCallArguments {
+                    index: call.index,
+                    arguments,
+                });
+            }
+        }
+    }
+}
+
+impl StreamDecoder for OpenAiStreamDecoder {
+    fn decode(&mut self, data: &str, on_delta: &mut dyn FnMut(StreamDelta)) -> Result<bool> {
+        if data == "[DONE]" {
+            return Ok(true);
+        }
+
+        let chunk: ChatChunk = serde_json::from_str(data)
+            .context(format!("Failed to parse stream chunk: {}", data))?;
+        for choice in chunk.choices {
+            self.apply(choice.delta, on_delta);
+        }
+        if let Some(usage) = chunk.usage {
+            self.usage = Some(usage.into());
+        }
+
+        Ok(false)
+    }
+
+    fn finish(self: Box<Self>) -> Completion {
+        let tool_calls: Vec<ToolCall> = self.tool_calls.into_values().collect();
+        Completion {
+            message: Message {
+                role: "assistant".to_string(),
+                content: self.content,
+                tool_calls: if tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(tool_calls)
+                },
+                tool_call_id: None,
+                is_error: false,
+            },
+            usage: self.usage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::sse::SseParser;
+
+    #[test]
+    fn stream_assembles_text_and_tool_calls() {
+        let chunks = [
+            r#"{"choices":[{"delta":{"content":"Let me "}}]}"#,
+            r#"{"choices":[{"delta":{"content":"look."}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","function":{"name":"read","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_b","function":{"name":"grep","arguments":"{\"pat"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"file_path\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"function":{"arguments":"tern\":\"é\"}"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":" \"a.rs\"}"}}]}}]}"#,
+            r#"{"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":5,"prompt_tokens_details":{"cached_tokens":8}}}"#,
+            "[DONE]",
+            r#"{"choices":[{"delta":{"content":"ignored"}}]}"#,
+        ];
+        let body: String = chunks.iter().map(|chunk| format!("data: {}\n\n", chunk)).collect();
+
+        let mut parser = SseParser::new();
+        let mut decoder = OpenAiBackend.stream_decoder();
+        let mut deltas = Vec::new();
+        let mut on_delta = |delta| deltas.push(delta);
+        'stream: for piece in body.as_bytes().chunks(7) {
+            for event in parser.feed(piece) {
+                if decoder.decode(&event.data, &mut on_delta).unwrap() {
+                    break 'stream;
+                }
+            }
+        }
+
+        let completion = decoder.finish();
+        assert_eq!(completion.message.content, "Let me look.");
+        let calls = completion.message.tool_calls.unwrap();
+        let calls: Vec<_> = calls
+            .iter()
+            .map(|call| (call.id.as_str(), call.function.name.as_str(), call.function.arguments.as_str()))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("call_a", "read", r#"{"file_path": "a.rs"}"#),
+                ("call_b", "grep", r#"{"pattern":"é"}"#),
+            ]
+        );
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.cached_tokens), (12, 5, 8));
+        assert!(matches!(&deltas[2], StreamDelta::ToolCallStarted { index: 0, name } if name == "read"));
+        let arguments = deltas
+            .iter()
+            .filter(|delta| matches!(delta, StreamDelta::ToolCallArguments { index: 1, .. }))
+            .count();
+        assert_eq!(arguments, 2);
+    }
+
+    #[test]
+    fn requests_leave_out_the_error_flag() {
+        let messages = [Message {
+            role: "tool".to_string(),
+            content: "Error: No such file".to_string(),
+            tool_calls: None,
+            tool_call_id: Some("call_a".to_string()),
+            is_error: true,
+        }];
+        let request = ChatRequest {
+            model: "gpt-4o",
+            messages: &messages,
+            tools: None,
+            tool_choice: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: false,
+        };
+        let body = OpenAiBackend.build_request(&request).unwrap();
+        assert_eq!(
+            body["messages"],
+            json!([{"role": "tool", "content": "Error: No such file", "tool_call_id": "call_a"}])
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use super::llm_client::{Message, Tool};
+use super::usage::is_model;
+
+/// Marks the system note that replaces compacted turns
+pub const SUMMARY_HEADER: &str = "[Summary of earlier conversation]";
+
+/// Per-message overhead for role markers and separators
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Longest slice of a single tool result included in a summary request
+const MAX_SUMMARY_TOOL_OUTPUT: usize = 2000;
+
+/// Context window sizes keyed by the `model_name`s in `litellm_config.yaml`,
+/// matched like the price table
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-3.5-turbo", 16_385),
+    ("claude-3", 200_000),
+];
+
+/// Used for models that are not in the table
+const DEFAULT_CONTEXT_WINDOW: usize = 128_000;
+
+/// Decides when the conversation must be compacted and which messages
+/// can be folded into a summary without breaking the transcript
+#[derive(Debug, Clone)]
+pub struct ContextManager {
+    /// Model context window in tokens
+    pub context_window: usize,
+    /// Fraction of the window at which compaction kicks in
+    pub threshold: f64,
+    /// Number of most recent user turns that are always kept verbatim
+    pub keep_recent_turns: usize,
+}
+
+/// Messages selected for compaction
+#[derive(Debug)]
+pub struct CompactionPlan {
+    /// Leading system prompt(s), kept as-is
+    pub prefix: Vec<Message>,
+    /// Messages to be replaced by the summary (including any older summary)
+    pub summarized: Vec<Message>,
+    /// Messages kept verbatim after the summary
+    pub kept: Vec<Message>,
+}
+
+impl ContextManager {
+    /// Compact once `threshold` of a `context_window` of tokens is used
+    pub fn new(context_window: usize, threshold: f64) -> Self {
+        Self {
+            context_window,
+            threshold,
+            keep_recent_turns: 2,
+        }
+    }
+
+    /// Token budget above which the conversation is compacted
+    pub fn limit(&self) -> usize {
+        (self.context_window as f64 * self.threshold) as usize
+    }
+
+    /// Completion tokens to ask for: `max_tokens`, but at most a quarter
+    /// of the window. Reserving 4096 of gpt-4's 8192 tokens for the answer
+    /// would leave too little for the prompt and compact on every turn.
+    pub fn output_budget(&self, max_tokens: u32) -> u32 {
+        max_tokens.min((self.context_window / 4) as u32)
+    }
+
+    /// Whether the request about to be sent crosses the threshold
+    pub fn needs_compaction(&self, messages: &[Message], tools: &[Tool], max_tokens: u32) -> bool {
+        let output = self.output_budget(max_tokens) as usize;
+        estimate_tokens(messages) + estimate_tool_tokens(tools) + output > self.limit()
+    }
+
+    /// Split the conversation into what is kept and what gets summarised.
+    ///
+    /// Cuts only happen in front of a user message, or in front of an
+    /// assistant message inside the current turn, so an assistant message
+    /// with `tool_calls` always stays together with its tool results.
+    /// Returns `None` if there is nothing worth compacting.
+    pub fn plan(&self, messages: &[Message]) -> Option<CompactionPlan> {
+        let prefix_len = messages
+            .iter()
+            .take_while(|m| m.role == "system" && !is_summary(m))
+            .count();
+        let body = &messages[prefix_len..];
+
+        let user_turns: Vec<usize> = body
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == "user")
+            .map(|(i, _)| i)
+            .collect();
+
+        let prefix = messages[..prefix_len].to_vec();
+
+        // Prefer dropping whole turns older than the most recent ones
+        if user_turns.len() > self.keep_recent_turns {
+            let cut = user_turns[user_turns.len() - self.keep_recent_turns];
+            return Some(CompactionPlan {
+                prefix,
+                summarized: body[..cut].to_vec(),
+                kept: body[cut..].to_vec(),
+            });
+        }
+
+        // Otherwise compact inside the current turn: keep its user message
+        // and the last two assistant steps with their tool results
+        let turn_start = *user_turns.last()?;
+        let steps: Vec<usize> = body
+            .iter()
+            .enumerate()
+            .skip(turn_start + 1)
+            .filter(|(_, m)| m.role == "assistant")
+            .map(|(i, _)| i)
+            .collect();
+        if steps.len() <= 2 {
+            return None;
+        }
+        let cut = steps[steps.len() - 2];
+
+        let mut summarized = body[..turn_start].to_vec();
+        summarized.extend_from_slice(&body[turn_start + 1..cut]);
+        let mut kept = vec![body[turn_start].clone()];
+        kept.extend_from_slice(&body[cut..]);
+
+        Some(CompactionPlan {
+            prefix,
+            summarized,
+            kept,
+        })
+    }
+}
+
+impl CompactionPlan {
+    /// Instructions and transcript for the summarisation request
+    pub fn summary_request(&self) -> Vec<Message> {
+        let mut transcript = String::new();
+        for message in &self.summarized {
+            transcript.push_str(&render_message(message));
+            transcript.push_str("\n\n");
+        }
+
+        vec![
+            Message {
+                role: "system".to_string(),
+                content: "You compress coding-agent conversations. Summarise the transcript \
+                    you are given so the agent can continue the work without it. Keep the \
+                    user's goals and constraints, decisions made, files read or changed \
+                    (with paths), commands run and their important results, errors \
+                    encountered, and any open tasks. Be concise and factual; do not add \
+                    commentary."
+                    .to_string(),
+                tool_calls: None,
+                tool_call_id: None,
+                is_error: false,
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!("Transcript to summarise:\n\n{}", transcript),
+                tool_calls: None,
+                tool_call_id: None,
+                is_error: false,
+            },
+        ]
+    }
+
+    /// Reassemble the conversation around the generated summary
+    pub fn apply(self, summary: &str) -> Vec<Message> {
+        let mut messages = self.prefix;
+        messages.push(Message {
+            role: "system".to_string(),
+            content: format!("{}\n{}", SUMMARY_HEADER, summary.trim()),
+            tool_calls: None,
+            tool_call_id: None,
+            is_error: false,
+        });
+        messages.extend(self.kept);
+        messages
+    }
+}
+
+/// Rough local token estimate (about four characters per token)
+pub fn estimate_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| {
+            let mut chars = message.content.len();
+            for call in message.tool_calls.iter().flatten() {
+                chars += call.function.name.len() + call.function.arguments.len();
+            }
+            chars / 4 + MESSAGE_OVERHEAD_TOKENS
+        })
+        .sum()
+}
+
+/// Tool definitions are sent with every request and count against the window
+pub fn estimate_tool_tokens(tools: &[Tool]) -> usize {
+    serde_json::to_string(tools).map(|s| s.len() / 4).unwrap_or(0)
+}
+
+/// Context window of the model, by the longest matching name
+pub fn context_window_for(model: &str) -> usize {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(name, _)| is_model(model, name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, window)| *window)
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+fn is_summary(message: &Message) -> bool {
+    message.role == "system" && message.content.starts_with(SUMMARY_HEADER)
+}
+
+fn render_message(message: &Message) -> String {
+    match message.role.as_str() {
+        "tool" => {
+            let mut content = message.content.clone();
+            if content.len() > MAX_SUMMARY_TOOL_OUTPUT {
+                let mut end = MAX_SUMMARY_TOOL_OUTPUT;
+                while !content.is_char_boundary(end) {
+                    end -= 1;
+                }
+                content.truncate(end);
+                content.push_str("\n[... truncated]");
+            }
+            format!("TOOL RESULT:\n{}", content)
+        }
+        "assistant" => {
+            let mut rendered = format!("ASSISTANT: {}", message.content);
+            for call in message.tool_calls.iter().flatten() {
+                rendered.push_str(&format!(
+                    "\nASSISTANT called {}({})",
+                    call.function.name, call.function.arguments
+                ));
+            }
+            rendered
+        }
+        "system" => format!("SYSTEM NOTE: {}", message.content),
+        _ => format!("USER: {}", message.content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::llm_client::{FunctionCall, ToolCall};
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            is_error: false,
+        }
+    }
+
+    /// An assistant step calling a tool for each of `ids` in parallel, then
+    /// the results
+    fn step(ids: &[&str]) -> Vec<Message> {
+        let calls = ids
+            .iter()
+            .map(|id| ToolCall {
+                id: id.to_string(),
+                tool_type: "function".to_string(),
+                function: FunctionCall {
+                    name: "read".to_string(),
+                    arguments: "{}".to_string(),
+                },
+            })
+            .collect();
+        let mut messages = vec![Message {
+            tool_calls: Some(calls),
+            ..message("assistant", "")
+        }];
+        for id in ids {
+            messages.push(Message {
+                tool_call_id: Some(id.to_string()),
+                ..message("tool", "output")
+            });
+        }
+        messages
+    }
+
+    /// Every tool result follows the call it answers, and every call has
+    /// its results, within `messages`
+    fn assert_paired(messages: &[Message]) {
+        let mut open: Vec<String> = Vec::new();
+        for message in messages {
+            match (&message.tool_call_id, &message.tool_calls) {
+                (Some(id), _) => {
+                    let position = open.iter().position(|open| open == id);
+                    assert!(position.is_some(), "result {} without its call", id);
+                    open.remove(position.unwrap());
+                }
+                (None, Some(calls)) => {
+                    assert!(open.is_empty(), "calls {:?} without results", open);
+                    open = calls.iter().map(|call| call.id.clone()).collect();
+                }
+                (None, None) => assert!(open.is_empty(), "calls {:?} without results", open),
+            }
+        }
+        assert!(open.is_empty(), "calls {:?} without results", open);
+    }
+
+    #[test]
+    fn whole_turns_are_cut_at_user_messages() {
+        let mut messages = vec![message("system", "Be helpful")];
+        for turn in ["a", "b", "c"] {
+            messages.push(message("user", turn));
+            messages.extend(step(&[&format!("{}1", turn), &format!("{}2", turn)]));
+            messages.push(message("assistant", "done"));
+        }
+
+        let plan = ContextManager::new(1000, 0.8).plan(&messages).unwrap();
+        assert_eq!(plan.prefix.len(), 1);
+        assert_eq!(plan.summarized.len(), 5);
+        assert_eq!(plan.summarized[0].content, "a");
+        assert_eq!(plan.kept[0].content, "b");
+        assert_paired(&plan.summarized);
+        assert_paired(&plan.kept);
+
+        let compacted = plan.apply("Read two files");
+        assert_eq!(compacted[0].content, "Be helpful");
+        assert!(compacted[1].content.starts_with(SUMMARY_HEADER));
+        assert_eq!(compacted.len(), 2 + 10);
+        assert_paired(&compacted);
+    }
+
+    #[test]
+    fn cuts_inside_a_turn_keep_parallel_calls_with_their_results() {
+        let mut messages = vec![message("system", "Be helpful"), message("user", "fix it")];
+        for i in 0..4 {
+            messages.extend(step(&[&format!("{}a", i), &format!("{}b", i), &format!("{}c", i)]));
+        }
+
+        let plan = ContextManager::new(1000, 0.8).plan(&messages).unwrap();
+        assert_eq!(plan.summarized.len(), 8);
+        assert_paired(&plan.summarized);
+        assert_eq!(plan.kept[0].content, "fix it");
+        assert_eq!(plan.kept[1].tool_calls.as_ref().unwrap()[0].id, "2a");
+        assert_eq!(plan.kept.len(), 1 + 8);
+        assert_paired(&plan.kept);
+
+        // Two steps are always kept, so there is nothing left to cut
+        let mut short = vec![message("user", "fix it")];
+        short.extend(step(&["x"]));
+        short.extend(step(&["y"]));
+        assert!(ContextManager::new(1000, 0.8).plan(&short).is_none());
+    }
+
+    #[test]
+    fn system_prompt_stays_and_old_summaries_are_folded_in() {
+        let mut messages = vec![
+            message("system", "Be helpful"),
+            message("system", &format!("{}\nEarlier work", SUMMARY_HEADER)),
+        ];
+        for turn in ["a", "b", "c"] {
+            messages.push(message("user", turn));
+            messages.push(message("assistant", "ok"));
+        }
+
+        let plan = ContextManager::new(1000, 0.8).plan(&messages).unwrap();
+        assert_eq!(plan.prefix.len(), 1);
+        assert!(is_summary(&plan.summarized[0]));
+        let compacted = plan.apply("Everything so far");
+        assert_eq!(compacted[0].content, "Be helpful");
+        assert_eq!(compacted.iter().filter(|m| is_summary(m)).count(), 1);
+    }
+
+    #[test]
+    fn windows_and_output_budget() {
+        assert_eq!(context_window_for("gpt-4"), 8_192);
+        assert_eq!(context_window_for("gpt-4-0613"), 8_192);
+        assert_eq!(context_window_for("gpt-4o"), 128_000);
+        assert_eq!(context_window_for("gpt-4o-mini"), 128_000);
+        assert_eq!(context_window_for("gpt-4-turbo"), 128_000);
+        assert_eq!(context_window_for("claude-3-5-sonnet-20241022"), 200_000);
+        assert_eq!(context_window_for("local-llama"), DEFAULT_CONTEXT_WINDOW);
+
+        let small = ContextManager::new(8_192, 0.8);
+        assert_eq!(small.output_budget(4096), 2048);
+        assert_eq!(ContextManager::new(128_000, 0.8).output_budget(4096), 4096);
+        // 4000 tokens of prompt still fit next to gpt-4's reduced reservation
+        let prompt = vec![message("user", &"x".repeat(16_000))];
+        assert!(!small.needs_compaction(&prompt, &[], 4096));
+    }
+}
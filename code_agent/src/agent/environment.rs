@@ -0,0 +1,174 @@
+//! The environment section at the start of the system prompt: working
+//! directory, git state, platform, date and a shallow listing of the
+//! project, so the model does not have to run tools to find out.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Directories listed below the working directory
+const LISTING_DEPTH: usize = 2;
+
+/// Most entries in the listing; the rest are counted
+const MAX_LISTING_ENTRIES: usize = 60;
+
+/// Most changed files named in the git summary; the rest are counted
+const MAX_CHANGED_FILES: usize = 20;
+
+/// Build outputs and dependencies, never worth listing
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "__pycache__", "venv"];
+
+/// The environment section for an agent working in `cwd`
+pub fn describe(cwd: &Path) -> String {
+    let mut section = format!("# Environment\n\nWorking directory: {}\n", cwd.display());
+    match git_state(cwd) {
+        Some(state) => section.push_str(&format!("Git repository: yes, {}\n", state)),
+        None => section.push_str("Git repository: no\n"),
+    }
+    section.push_str(&format!(
+        "Platform: {} ({})\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    ));
+    section.push_str(&format!(
+        "Shell: {}\n",
+        std::env::var("SHELL").unwrap_or_else(|_| "unknown".to_string())
+    ));
+    section.push_str(&format!(
+        "Today's date: {}\n",
+        chrono::Local::now().format("%Y-%m-%d")
+    ));
+
+    let listing = listing(cwd);
+    if !listing.is_empty() {
+        section.push_str(&format!(
+            "\nFiles in the working directory ({} levels, hidden files left out):\n{}",
+            LISTING_DEPTH, listing
+        ));
+    }
+    section.truncate(section.trim_end().len());
+    section
+}
+
+fn git(cwd: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(cwd).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+/// Branch and uncommitted changes, or None outside a repository
+fn git_state(cwd: &Path) -> Option<String> {
+    let root = git(cwd, &["rev-parse", "--show-toplevel"])?;
+    let mut state = match git(cwd, &["branch", "--show-current"]).filter(|b| !b.is_empty()) {
+        Some(branch) => format!("branch {}", branch),
+        None => match git(cwd, &["rev-parse", "--short", "HEAD"]) {
+            Some(commit) => format!("detached at {}", commit),
+            None => "no commits yet".to_string(),
+        },
+    };
+    // Status paths are relative to the root
+    if Path::new(&root) != cwd {
+        state.push_str(&format!(", root {}", root));
+    }
+    let status = git(cwd, &["status", "--porcelain"]).unwrap_or_default();
+    Some(format!("{}; {}", state, summarize_status(&status)))
+}
+
+/// Counts of changed files from `git status --porcelain`, then the first
+/// few of them
+fn summarize_status(porcelain: &str) -> String {
+    let lines: Vec<&str> = porcelain.lines().filter(|line| line.len() > 3).collect();
+    if lines.is_empty() {
+        return "working tree clean".to_string();
+    }
+
+    let untracked = lines.iter().filter(|line| line.starts_with("??")).count();
+    let changed = lines.len() - untracked;
+    let mut counts = Vec::new();
+    if changed > 0 {
+        counts.push(format!("{} changed", changed));
+    }
+    if untracked > 0 {
+        counts.push(format!("{} untracked", untracked));
+    }
+
+    let mut summary = counts.join(", ");
+    for line in lines.iter().take(MAX_CHANGED_FILES) {
+        summary.push_str(&format!("\n  {}", line));
+    }
+    if lines.len() > MAX_CHANGED_FILES {
+        summary.push_str(&format!("\n  ... and {} more", lines.len() - MAX_CHANGED_FILES));
+    }
+    summary
+}
+
+/// Directories first, then files, each sorted by name and indented by depth
+fn listing(cwd: &Path) -> String {
+    let mut entries = Vec::new();
+    let mut omitted = 0;
+    list_dir(cwd, 0, &mut entries, &mut omitted);
+
+    let mut listing = entries.join("\n");
+    if omitted > 0 {
+        listing.push_str(&format!("\n... and {} more", omitted));
+    }
+    listing
+}
+
+fn list_dir(dir: &Path, depth: usize, entries: &mut Vec<String>, omitted: &mut usize) {
+    let Ok(read) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut children: Vec<(bool, String)> = read
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            (!is_dir, entry.file_name().to_string_lossy().into_owned())
+        })
+        .filter(|(_, name)| !name.starts_with('.'))
+        .collect();
+    children.sort();
+
+    for (is_file, name) in children {
+        if entries.len() >= MAX_LISTING_ENTRIES {
+            *omitted += 1;
+            continue;
+        }
+        let indent = "  ".repeat(depth);
+        if is_file {
+            entries.push(format!("{}{}", indent, name));
+            continue;
+        }
+        entries.push(format!("{}{}/", indent, name));
+        if depth + 1 < LISTING_DEPTH && !SKIPPED_DIRS.contains(&name.as_str()) {
+            list_dir(&dir.join(&name), depth + 1, entries, omitted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_and_git_summary_are_bounded() {
+        let root = std::env::temp_dir().join(format!("code_agent_environment_{}", std::process::id()));
+        fs::create_dir_all(root.join("src").join("deep")).unwrap();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+        fs::write(root.join("src").join("main.rs"), "").unwrap();
+        fs::write(root.join("src").join("deep").join("skipped.rs"), "").unwrap();
+        let listed = listing(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(listed, "src/\n  deep/\n  main.rs\ntarget/\nCargo.toml");
+
+        assert_eq!(summarize_status(""), "working tree clean");
+        let porcelain: String = (0..25).map(|i| format!(" M file{}.rs\n", i)).collect();
+        let summary = summarize_status(&format!("{}?? notes.txt\n", porcelain));
+        assert!(summary.starts_with("25 changed, 1 untracked\n   M file0.rs\n"));
+        assert!(summary.ends_with("\n  ... and 6 more"));
+    }
+}
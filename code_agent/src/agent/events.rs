@@ -0,0 +1,99 @@
+use super::llm_client::Usage;
+use serde::Serialize;
+
+/// Receives the events of an `AgentLoop`
+pub type Observer = Box<dyn FnMut(&AgentEvent) + Send>;
+
+/// Progress of a turn, reported to the observers added with
+/// `AgentLoop::on_event` as it happens
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    TurnStarted {
+        prompt: String,
+    },
+    /// An LLM request is about to be made
+    Iteration {
+        number: usize,
+        max: usize,
+    },
+    /// An LLM request failed and is retried after a delay
+    Retrying {
+        reason: String,
+        /// Number of the coming attempt, counting the first request as 1
+        attempt: u32,
+        max_attempts: u32,
+        delay_secs: f64,
+        message: String,
+    },
+    /// Something went wrong that does not affect the turn, e.g. the
+    /// session file could not be written
+    Warning {
+        message: String,
+    },
+    /// Older history was summarised to free up context
+    Compacted {
+        messages: usize,
+        tokens_before: usize,
+        tokens_after: usize,
+    },
+    /// A piece of assistant text, while a response is streamed
+    TextDelta {
+        text: String,
+    },
+    /// The model started a tool call, while a response is streamed
+    ToolCallStarted {
+        index: usize,
+        name: String,
+    },
+    /// A fragment of a streamed tool call's JSON arguments
+    ToolArgumentsDelta {
+        index: usize,
+        arguments: String,
+    },
+    /// Text of a complete assistant response, with or without tool calls
+    Text {
+        text: String,
+    },
+    /// The response asks for tool calls; a `ToolCall` follows for each
+    ToolCallsRequested {
+        count: usize,
+    },
+    ToolCall {
+        id: String,
+        name: String,
+        /// Arguments as the model sent them (a JSON string)
+        arguments: String,
+    },
+    /// The permission policy refused a call; its `ToolResult` follows
+    PermissionDenied {
+        id: String,
+        name: String,
+        reason: String,
+    },
+    ToolResult {
+        id: String,
+        name: String,
+        success: bool,
+        /// Output of a successful call, or the error
+        output: String,
+    },
+    /// Token usage of the turn, sent when it ends
+    Usage {
+        tokens: Usage,
+        requests: u32,
+        /// Estimated cost in USD, if the model's price is known
+        cost_usd: Option<f64>,
+        summary: String,
+    },
+    /// The turn ended with this answer
+    Finished {
+        answer: String,
+    },
+    /// The turn was cancelled
+    Interrupted,
+    /// The turn failed
+    Error {
+        message: String,
+    },
+}
@@ -0,0 +1,268 @@
+//! Instruction files (`AGENTS.md`, `CODE_AGENT.md`) added to the system
+//! prompt: the user's in `~/.code_agent/`, then the project's from the
+//! repository root down to the working directory. A line holding only
+//! `@path` is replaced by the contents of that file. The project's files
+//! may only import files inside the repository; only the user's may use
+//! `~/` or import from anywhere else.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Names of instruction files, in the order they are read in a directory
+pub const FILE_NAMES: &[&str] = &["AGENTS.md", "CODE_AGENT.md"];
+
+/// Longer files are cut off, so one file cannot fill the context
+const MAX_FILE_BYTES: usize = 64 * 1024;
+
+/// How deep `@path` imports may nest
+const MAX_IMPORT_DEPTH: usize = 5;
+
+/// An instruction file and the files it imported
+#[derive(Debug, Clone)]
+pub struct InstructionFile {
+    pub path: PathBuf,
+    /// How the file is named in the system prompt
+    pub label: String,
+    /// Contents with imports resolved
+    pub content: String,
+    pub imports: Vec<PathBuf>,
+}
+
+/// The instruction files that apply in a directory, least specific first
+#[derive(Debug, Clone, Default)]
+pub struct Instructions {
+    pub files: Vec<InstructionFile>,
+}
+
+impl Instructions {
+    /// Files of the user and of the project containing `cwd`
+    pub fn load(cwd: &Path) -> Result<Self> {
+        let mut instructions = Self::default();
+        if let Some(home) = dirs::home_dir() {
+            let dir = home.join(".code_agent");
+            instructions.read_dir(&dir, None, |name| format!("~/.code_agent/{}", name))?;
+        }
+
+        let root = repository_root(cwd).unwrap_or(cwd);
+        let scope = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let mut dirs: Vec<&Path> = cwd.ancestors().take_while(|dir| dir.starts_with(root)).collect();
+        dirs.reverse();
+        for dir in dirs {
+            let relative = dir.strip_prefix(root).unwrap_or(dir).to_path_buf();
+            instructions.read_dir(dir, Some(&scope), |name| {
+                relative.join(name).display().to_string()
+            })?;
+        }
+        Ok(instructions)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// `system_prompt` followed by each file's contents under a header
+    /// naming the file
+    pub fn apply(&self, system_prompt: &str) -> String {
+        if self.is_empty() {
+            return system_prompt.to_string();
+        }
+
+        let mut prompt = format!(
+            "{}\n\n# Instructions\n\nThe user and the project give these instructions. \
+             Files listed later are more specific and take precedence.",
+            system_prompt
+        );
+        for file in &self.files {
+            prompt.push_str(&format!("\n\n## {}\n\n{}", file.label, file.content.trim()));
+        }
+        prompt
+    }
+
+    /// Read the instruction files in `dir`; with a `scope`, imports must
+    /// stay inside that directory
+    fn read_dir(
+        &mut self,
+        dir: &Path,
+        scope: Option<&Path>,
+        label: impl Fn(&str) -> String,
+    ) -> Result<()> {
+        for name in FILE_NAMES {
+            let path = dir.join(name);
+            if !path.is_file() || self.files.iter().any(|file| same_file(&file.path, &path)) {
+                continue;
+            }
+            let mut imports = Vec::new();
+            let content = read_with_imports(&path, scope, &mut vec![path.clone()], &mut imports)?;
+            self.files.push(InstructionFile {
+                label: label(name),
+                path,
+                content,
+                imports,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Closest directory at or above `dir` that holds a `.git` entry
+fn repository_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|dir| dir.join(".git").exists())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Read `path`, replacing `@path` lines outside code blocks by the files
+/// they name. `stack` holds the files being read, to stop import cycles.
+fn read_with_imports(
+    path: &Path,
+    scope: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+    imports: &mut Vec<PathBuf>,
+) -> Result<String> {
+    let mut content =
+        fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    if content.len() > MAX_FILE_BYTES {
+        let mut end = MAX_FILE_BYTES;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+        content.push_str("\n[truncated]");
+    }
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut resolved = String::new();
+    let mut in_code_block = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        let target = line
+            .trim()
+            .strip_prefix('@')
+            .filter(|target| !in_code_block && !target.is_empty())
+            .filter(|target| !target.contains(char::is_whitespace))
+            .and_then(|target| resolve_import(path, base, target, scope))
+            .filter(|target| target.is_file())
+            .filter(|target| stack.len() < MAX_IMPORT_DEPTH && !stack.iter().any(|p| same_file(p, target)));
+
+        match target {
+            Some(target) => {
+                stack.push(target.clone());
+                imports.push(target.clone());
+                let imported = read_with_imports(&target, scope, stack, imports)?;
+                stack.pop();
+                resolved.push_str(imported.trim_end());
+            }
+            _ => resolved.push_str(line),
+        }
+        resolved.push('\n');
+    }
+    Ok(resolved)
+}
+
+/// The file `target` names when imported from `path` in `base`, or None
+/// if it does not exist or is outside `scope`. Symlinks are followed
+/// before checking, so a link cannot lead out of the repository either.
+fn resolve_import(path: &Path, base: &Path, target: &str, scope: Option<&Path>) -> Option<PathBuf> {
+    let resolved = match (target.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) if scope.is_none() => home.join(rest),
+        (Some(_), _) => {
+            eprintln!(
+                "Warning: ignoring @{} in {}: only ~/.code_agent files may import from ~",
+                target,
+                path.display()
+            );
+            return None;
+        }
+        _ => base.join(target),
+    };
+    let resolved = resolved.canonicalize().ok()?;
+    match scope {
+        Some(scope) if !resolved.starts_with(scope) => {
+            eprintln!(
+                "Warning: ignoring @{} in {}: it is outside the repository",
+                target,
+                path.display()
+            );
+            None
+        }
+        _ => Some(resolved),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_from_root_to_cwd_with_imports() {
+        let root = std::env::temp_dir().join(format!("code_agent_instructions_{}", std::process::id()));
+        let cwd = root.join("crates").join("app");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(&cwd).unwrap();
+        fs::write(root.join("AGENTS.md"), "Root rules\n@docs/style.md\n```\n@docs/style.md\n```\n").unwrap();
+        fs::write(root.join("docs").join("style.md"), "Use tabs\n@../AGENTS.md\n").unwrap();
+        fs::write(cwd.join("CODE_AGENT.md"), "App rules\n@missing.md\n").unwrap();
+
+        let instructions = Instructions::load(&cwd).unwrap();
+        let style = root.join("docs").join("style.md").canonicalize().unwrap();
+        let project: Vec<_> = instructions
+            .files
+            .iter()
+            .filter(|file| file.path.starts_with(&root))
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(project.len(), 2);
+        assert_eq!(project[0].label, "AGENTS.md");
+        // Imported once; the cycle back to AGENTS.md and the code block are left alone
+        assert_eq!(
+            project[0].content,
+            "Root rules\nUse tabs\n@../AGENTS.md\n```\n@docs/style.md\n```\n"
+        );
+        assert_eq!(project[0].imports, [style]);
+        assert_eq!(project[1].label, "crates/app/CODE_AGENT.md");
+        assert_eq!(project[1].content, "App rules\n@missing.md\n");
+
+        let prompt = Instructions { files: project.into_iter().cloned().collect() }.apply("Base");
+        assert!(prompt.starts_with("Base\n\n# Instructions"));
+        assert!(prompt.contains("## AGENTS.md\n\nRoot rules"));
+        assert!(prompt.ends_with("## crates/app/CODE_AGENT.md\n\nApp rules\n@missing.md"));
+    }
+
+    #[test]
+    fn project_imports_stay_in_the_repository() {
+        let dir = std::env::temp_dir().join(format!("code_agent_escape_{}", std::process::id()));
+        let root = dir.join("repo");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(dir.join("secret.txt"), "SECRET").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("link.md")).unwrap();
+        fs::write(root.join("notes.md"), "Notes").unwrap();
+        let secret = dir.join("secret.txt").display().to_string();
+        let agents = format!("@notes.md\n@../secret.txt\n@{}\n@link.md\n@~/.bashrc\n", secret);
+        fs::write(root.join("AGENTS.md"), &agents).unwrap();
+
+        let instructions = Instructions::load(&root).unwrap();
+        let project: Vec<_> = instructions
+            .files
+            .iter()
+            .filter(|file| file.path.starts_with(&root))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(project.len(), 1);
+        assert_eq!(
+            project[0].content,
+            format!("Notes\n@../secret.txt\n@{}\n@link.md\n@~/.bashrc\n", secret)
+        );
+        assert_eq!(project[0].imports.len(), 1);
+    }
+}
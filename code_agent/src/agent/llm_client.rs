@@ -1,6 +1,10 @@
+use super::backend::{self, Backend};
+use super::retry::{LlmError, RetryNotice, RetryPolicy};
+use super::sse::SseParser;
+use crate::config::LlmConfig;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -10,6 +14,9 @@ pub struct Message {
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
+    /// The tool call this message answers failed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_error: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,102 +47,272 @@ pub struct FunctionDefinition {
     pub parameters: serde_json::Value,
 }
 
+/// Token counts reported by the provider for one request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// All input tokens, including those served from the prompt cache
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Portion of `prompt_tokens` read from the provider's prompt cache
+    pub cached_tokens: u64,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cached_tokens += other.cached_tokens;
+    }
+}
+
+/// An assistant message together with the tokens it consumed
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub message: Message,
+    /// `None` if the server did not report usage
+    pub usage: Option<Usage>,
+}
+
+/// Provider-neutral chat request; OpenAI-compatible backends send it as-is
 #[derive(Debug, Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<Message>,
+pub struct ChatRequest<'a> {
+    pub model: &'a str,
+    pub messages: &'a [Message],
     #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<Tool>>,
+    pub tools: Option<&'a [Tool]>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    tool_choice: Option<String>,
+    pub tool_choice: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    max_tokens: Option<u32>,
+    pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
 }
 
-#[derive(Debug, Deserialize)]
-struct Choice {
-    message: Message,
-    finish_reason: String,
+/// Incremental update surfaced while a streaming response is being received
+#[derive(Debug, Clone)]
+pub enum StreamDelta {
+    /// A piece of assistant text
+    Text(String),
+    /// The model started a new tool call
+    ToolCallStarted { index: usize, name: String },
+    /// A fragment of a tool call's JSON arguments
+    ToolCallArguments { index: usize, arguments: String },
 }
 
 pub struct LlmClient {
     client: reqwest::Client,
+    backend: Box<dyn Backend>,
     base_url: String,
     api_key: String,
     model: String,
+    temperature: f32,
+    top_p: Option<f32>,
+    retry_policy: RetryPolicy,
 }
 
 impl LlmClient {
-    pub fn new() -> Result<Self> {
-        let base_url = env::var("LITELLM_BASE_URL")
-            .unwrap_or_else(|_| "http://localhost:4000".to_string());
-        let api_key = env::var("LITELLM_API_KEY")
-            .unwrap_or_else(|_| "sk-1234".to_string());
-        let model = env::var("LITELLM_MODEL")
-            .unwrap_or_else(|_| "gpt-4".to_string());
+    /// Create a client for the configured backend, endpoint and model
+    pub fn new(config: &LlmConfig) -> Result<Self> {
+        let backend = backend::from_name(&config.backend)?;
+        let api_key = config.api_key.clone().context(format!(
+            "llm.api_key must be set for the {} backend",
+            backend.name()
+        ))?;
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            // Applies between reads, so long streams are fine as long as they keep flowing
+            .read_timeout(Duration::from_secs(600))
+            .build()
+            .context("Failed to build HTTP client")?;
 
         Ok(Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client,
+            backend,
+            base_url: config.base_url.clone(),
             api_key,
-            model,
+            model: config.model.clone(),
+            temperature: config.temperature,
+            top_p: config.top_p,
+            retry_policy: RetryPolicy {
+                max_retries: config.max_retries,
+                budget: Duration::from_secs(config.retry_budget_secs),
+                ..RetryPolicy::default()
+            },
         })
     }
 
+    /// Name of the model requests are sent to
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Send later requests to `model`, on the same backend and endpoint
+    pub fn set_model(&mut self, model: String) {
+        self.model = model;
+    }
+
+    /// Send a request and wait for the whole response. `on_retry` hears
+    /// about failed attempts that are retried.
     pub async fn chat(
         &self,
-        messages: Vec<Message>,
-        tools: Option<Vec<Tool>>,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
         max_tokens: Option<u32>,
-    ) -> Result<Message> {
-        let url = format!("{}/chat/completions", self.base_url);
+        mut on_retry: impl FnMut(RetryNotice) + Send,
+    ) -> Result<Completion> {
+        let request = ChatRequest {
+            model: &self.model,
+            messages,
+            tools,
+            tool_choice: None,
+            max_tokens,
+            temperature: Some(self.temperature),
+            top_p: self.top_p,
+            stream: false,
+        };
+
+        let response = self.send(&request, &mut on_retry).await?;
+
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| LlmError::from_transport(&e))
+            .context("Failed to parse LLM response")?;
 
+        self.backend.parse_response(body)
+    }
+
+    /// Like `chat`, but requests a server-sent event stream and reports
+    /// text and tool-call fragments through `on_delta` as they arrive.
+    ///
+    /// The returned completion is identical in shape to the one `chat` produces.
+    pub async fn chat_stream<F>(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        max_tokens: Option<u32>,
+        mut on_delta: F,
+        mut on_retry: impl FnMut(RetryNotice) + Send,
+    ) -> Result<Completion>
+    where
+        F: FnMut(StreamDelta),
+    {
         let request = ChatRequest {
-            model: self.model.clone(),
+            model: &self.model,
             messages,
             tools,
             tool_choice: None,
             max_tokens,
-            temperature: Some(0.7),
+            temperature: Some(self.temperature),
+            top_p: self.top_p,
+            stream: true,
         };
 
-        let response = self
+        let mut response = self.send(&request, &mut on_retry).await?;
+
+        let mut parser = SseParser::new();
+        let mut decoder = self.backend.stream_decoder();
+        let mut done = false;
+
+        while !done {
+            let Some(chunk) = response
+                .chunk()
+                .await
+                .map_err(|e| LlmError::from_transport(&e))
+                .context("Failed to read LLM response stream")?
+            else {
+                break;
+            };
+
+            for event in parser.feed(&chunk) {
+                done = decoder.decode(&event.data, &mut on_delta)?;
+                if done {
+                    break;
+                }
+            }
+        }
+
+        if !done {
+            if let Some(event) = parser.finish() {
+                decoder.decode(&event.data, &mut on_delta)?;
+            }
+        }
+
+        Ok(decoder.finish())
+    }
+
+    /// Send a request, retrying transient failures according to the retry
+    /// policy. Only establishing the response is retried: once a stream has
+    /// started delivering deltas, replaying it would duplicate output.
+    async fn send(
+        &self,
+        request: &ChatRequest<'_>,
+        on_retry: &mut (dyn FnMut(RetryNotice) + Send),
+    ) -> Result<reqwest::Response> {
+        let url = self.backend.endpoint(&self.base_url);
+        let body = self.backend.build_request(request)?;
+
+        let mut attempt = 0;
+        let mut waited = Duration::ZERO;
+
+        loop {
+            let error = match self.send_once(&url, &body, request.stream).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            attempt += 1;
+            let Some(delay) = self.retry_policy.delay_for(&error, attempt, waited) else {
+                return Err(error.into());
+            };
+
+            on_retry(self.retry_policy.notice(&error, attempt, delay));
+            tokio::time::sleep(delay).await;
+            waited += delay;
+        }
+    }
+
+    async fn send_once(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+        stream: bool,
+    ) -> std::result::Result<reqwest::Response, LlmError> {
+        let mut builder = self
             .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to send request to LLM")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await?;
-            anyhow::bail!(
-                "LLM request failed with status {}: {}",
-                status,
-                error_text
-            );
+            .post(url)
+            .header("Content-Type", "application/json");
+        for (name, value) in self.backend.headers(&self.api_key) {
+            builder = builder.header(name, value);
+        }
+        if stream {
+            builder = builder.header("Accept", "text/event-stream");
         }
 
-        let chat_response: ChatResponse = response
-            .json()
+        let response = builder
+            .json(body)
+            .send()
             .await
-            .context("Failed to parse LLM response")?;
+            .map_err(|e| LlmError::from_transport(&e))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
 
-        chat_response
-            .choices
-            .into_iter()
-            .next()
-            .map(|choice| choice.message)
-            .context("No response from LLM")
+        let headers = response.headers().clone();
+        let error_text = response.text().await.unwrap_or_default();
+        Err(LlmError::from_response(status, &headers, error_text))
     }
 }
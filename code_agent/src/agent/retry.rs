@@ -0,0 +1,386 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+/// Classified failure of an LLM request
+///
+/// Returned (wrapped in `anyhow::Error`) by `LlmClient`, so callers can
+/// `downcast_ref::<LlmError>()` to decide how to react.
+#[derive(Debug, Clone)]
+pub enum LlmError {
+    /// 429 from the provider or proxy
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// 5xx, 408 or provider overload
+    Server { status: u16, message: String },
+    /// Connection refused, reset or dropped before a response arrived
+    Connection(String),
+    /// The request timed out
+    Timeout(String),
+    /// 401/403: bad or missing API key
+    Authentication { status: u16, message: String },
+    /// The prompt no longer fits into the model's context window
+    ContextLengthExceeded(String),
+    /// Any other 4xx the server will reject again if retried
+    BadRequest { status: u16, message: String },
+}
+
+impl LlmError {
+    /// Classify a non-success HTTP response
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, body: String) -> Self {
+        let code = status.as_u16();
+        match code {
+            429 => LlmError::RateLimited {
+                retry_after: parse_retry_after(headers),
+                message: body,
+            },
+            401 | 403 => LlmError::Authentication {
+                status: code,
+                message: body,
+            },
+            400 | 413 if is_context_length_message(&body) => LlmError::ContextLengthExceeded(body),
+            408 => LlmError::Server {
+                status: code,
+                message: body,
+            },
+            _ if status.is_server_error() => LlmError::Server {
+                status: code,
+                message: body,
+            },
+            _ => LlmError::BadRequest {
+                status: code,
+                message: body,
+            },
+        }
+    }
+
+    /// Classify a transport-level failure
+    pub fn from_transport(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            LlmError::Timeout(error.to_string())
+        } else {
+            LlmError::Connection(error_chain(error))
+        }
+    }
+
+    /// Whether repeating the identical request may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            LlmError::RateLimited { .. }
+                | LlmError::Server { .. }
+                | LlmError::Connection(_)
+                | LlmError::Timeout(_)
+        )
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LlmError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    fn short_reason(&self) -> String {
+        match self {
+            LlmError::RateLimited { .. } => "rate limited".to_string(),
+            LlmError::Server { status, .. } => format!("server error {}", status),
+            LlmError::Connection(_) => "connection error".to_string(),
+            LlmError::Timeout(_) => "timed out".to_string(),
+            LlmError::Authentication { status, .. } => format!("authentication failed ({})", status),
+            LlmError::ContextLengthExceeded(_) => "context length exceeded".to_string(),
+            LlmError::BadRequest { status, .. } => format!("bad request ({})", status),
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::RateLimited { message, .. } => {
+                write!(f, "LLM request was rate limited (429): {}", message)
+            }
+            LlmError::Server { status, message } => {
+                write!(f, "LLM server error ({}): {}", status, message)
+            }
+            LlmError::Connection(message) => write!(f, "Failed to connect to LLM: {}", message),
+            LlmError::Timeout(message) => write!(f, "LLM request timed out: {}", message),
+            LlmError::Authentication { status, message } => {
+                write!(f, "LLM authentication failed ({}): {}", status, message)
+            }
+            LlmError::ContextLengthExceeded(message) => {
+                write!(f, "Conversation exceeds the model's context window: {}", message)
+            }
+            LlmError::BadRequest { status, message } => {
+                write!(f, "LLM request failed with status {}: {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+/// How failed LLM requests are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each further attempt
+    pub initial_backoff: Duration,
+    /// Upper bound for a single computed backoff
+    pub max_backoff: Duration,
+    /// Total time that may be spent waiting between attempts
+    pub budget: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            budget: Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (1-based), or `None` to give up.
+    ///
+    /// A server-provided `Retry-After` wins over the computed backoff;
+    /// otherwise exponential backoff with equal jitter is used.
+    pub fn delay_for(&self, error: &LlmError, attempt: u32, waited: Duration) -> Option<Duration> {
+        if !error.is_retryable() || attempt > self.max_retries {
+            return None;
+        }
+
+        let delay = error.retry_after().unwrap_or_else(|| {
+            let exponential = self
+                .initial_backoff
+                .saturating_mul(2u32.saturating_pow(attempt - 1))
+                .min(self.max_backoff);
+            let half = exponential / 2;
+            half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+        });
+
+        // Saturating, since the server's delay can be arbitrarily large
+        if waited.saturating_add(delay) > self.budget {
+            return None;
+        }
+        Some(delay)
+    }
+
+    /// Notice of the retry that follows failed attempt number `attempt`
+    pub fn notice(&self, error: &LlmError, attempt: u32, delay: Duration) -> RetryNotice {
+        RetryNotice {
+            reason: error.short_reason(),
+            attempt: attempt + 1,
+            max_attempts: self.max_retries + 1,
+            delay,
+        }
+    }
+}
+
+/// A failed LLM request that is about to be retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryNotice {
+    /// Why the last attempt failed, e.g. "rate limited"
+    pub reason: String,
+    /// Number of the coming attempt, counting the first request as 1
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+
+impl fmt::Display for RetryNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "LLM request {}; retrying in {:.1}s (attempt {}/{})",
+            self.reason,
+            self.delay.as_secs_f64(),
+            self.attempt,
+            self.max_attempts
+        )
+    }
+}
+
+/// Parse `Retry-After`, in seconds or as an HTTP date, and the
+/// `retry-after-ms` extension some OpenAI-compatible servers send
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(delay) = headers
+        .get("retry-after-ms")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .and_then(|ms| seconds(ms / 1000.0))
+    {
+        return Some(delay);
+    }
+
+    let value = headers.get("retry-after")?.to_str().ok()?;
+    retry_after_value(value, Utc::now())
+}
+
+/// A `Retry-After` value as a delay from `now`; a date in the past means
+/// retrying right away
+fn retry_after_value(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return seconds(secs);
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// A server-provided number of seconds as a delay; `inf`, `NaN` and values
+/// too large for a `Duration` are ignored
+fn seconds(secs: f64) -> Option<Duration> {
+    if !secs.is_finite() {
+        return None;
+    }
+    Duration::try_from_secs_f64(secs.max(0.0)).ok()
+}
+
+fn is_context_length_message(body: &str) -> bool {
+    let body = body.to_lowercase();
+    [
+        "context_length_exceeded",
+        "context length",
+        "context window",
+        "maximum context",
+        "prompt is too long",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|needle| body.contains(needle))
+}
+
+/// Flatten an error and its sources into one line, since the interesting
+/// part of a reqwest error ("connection refused") is usually a source
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn response(status: u16, headers: &[(&'static str, &'static str)], body: &str) -> LlmError {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_static(value));
+        }
+        LlmError::from_response(StatusCode::from_u16(status).unwrap(), &map, body.to_string())
+    }
+
+    #[test]
+    fn responses_are_classified() {
+        assert!(matches!(
+            response(429, &[("retry-after", "3")], "slow down"),
+            LlmError::RateLimited { retry_after: Some(d), .. } if d == Duration::from_secs(3)
+        ));
+        assert!(matches!(
+            response(429, &[("retry-after-ms", "250"), ("retry-after", "3")], ""),
+            LlmError::RateLimited { retry_after: Some(d), .. } if d == Duration::from_millis(250)
+        ));
+        assert!(matches!(response(401, &[], ""), LlmError::Authentication { status: 401, .. }));
+        assert!(matches!(response(403, &[], ""), LlmError::Authentication { status: 403, .. }));
+        assert!(matches!(
+            response(400, &[], r#"{"error": {"code": "context_length_exceeded"}}"#),
+            LlmError::ContextLengthExceeded(_)
+        ));
+        assert!(matches!(response(400, &[], "bad field"), LlmError::BadRequest { status: 400, .. }));
+        assert!(matches!(response(404, &[], ""), LlmError::BadRequest { status: 404, .. }));
+        assert!(matches!(response(408, &[], ""), LlmError::Server { status: 408, .. }));
+        assert!(matches!(response(529, &[], "overloaded"), LlmError::Server { status: 529, .. }));
+
+        assert!(response(503, &[], "").is_retryable());
+        assert!(!response(401, &[], "").is_retryable());
+        assert!(!response(400, &[], "prompt is too long").is_retryable());
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(retry_after_value(" 2.5 ", now), Some(Duration::from_millis(2500)));
+        assert_eq!(retry_after_value("-1", now), Some(Duration::ZERO));
+        assert_eq!(
+            retry_after_value("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(retry_after_value("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(retry_after_value("soon", now), None);
+        assert_eq!(retry_after_value("inf", now), None);
+        assert_eq!(retry_after_value("NaN", now), None);
+        assert_eq!(retry_after_value("1e400", now), None);
+        assert_eq!(retry_after_value("1e300", now), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after-ms", "NaN".parse().unwrap());
+        headers.insert("retry-after", "3".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(3)));
+        headers.insert("retry-after-ms", "1500".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn delays_back_off_with_jitter_within_the_budget() {
+        let policy = RetryPolicy::default();
+        let server = LlmError::Server {
+            status: 502,
+            message: String::new(),
+        };
+        for _ in 0..50 {
+            let first = policy.delay_for(&server, 1, Duration::ZERO).unwrap();
+            assert!(first >= Duration::from_millis(250) && first <= Duration::from_millis(500));
+            let third = policy.delay_for(&server, 3, Duration::ZERO).unwrap();
+            assert!(third >= Duration::from_secs(1) && third <= Duration::from_secs(2));
+            // Capped at max_backoff
+            let late = RetryPolicy {
+                max_retries: 20,
+                ..RetryPolicy::default()
+            };
+            let capped = late.delay_for(&server, 12, Duration::ZERO).unwrap();
+            assert!(capped >= Duration::from_secs(15) && capped <= Duration::from_secs(30));
+        }
+
+        assert_eq!(policy.delay_for(&server, 5, Duration::ZERO).map(|_| ()), None);
+        let unauthorized = LlmError::Authentication {
+            status: 401,
+            message: String::new(),
+        };
+        assert_eq!(policy.delay_for(&unauthorized, 1, Duration::ZERO), None);
+
+        let limited = LlmError::RateLimited {
+            retry_after: Some(Duration::from_secs(60)),
+            message: String::new(),
+        };
+        assert_eq!(policy.delay_for(&limited, 1, Duration::ZERO), Some(Duration::from_secs(60)));
+        // The server's delay would overrun the budget
+        assert_eq!(policy.delay_for(&limited, 2, Duration::from_secs(61)), None);
+        let huge = LlmError::RateLimited {
+            retry_after: retry_after_value("1e19", Utc::now()),
+            message: String::new(),
+        };
+        assert!(huge.retry_after().is_some());
+        assert_eq!(policy.delay_for(&huge, 1, Duration::from_secs(1)), None);
+
+        let notice = policy.notice(&limited, 1, Duration::from_secs(60));
+        assert_eq!(notice.to_string(), "LLM request rate limited; retrying in 60.0s (attempt 2/5)");
+    }
+}
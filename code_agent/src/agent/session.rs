@@ -0,0 +1,474 @@
+use super::llm_client::Message;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Metadata written as the first line of every session file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionHeader {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub cwd: String,
+    pub model: String,
+}
+
+/// One line of a session file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Session(SessionHeader),
+    Message {
+        timestamp: DateTime<Utc>,
+        message: Message,
+    },
+    /// The history was rewritten (compaction or `clear`)
+    Replace {
+        timestamp: DateTime<Utc>,
+        messages: Vec<Message>,
+    },
+}
+
+/// A session loaded back from disk
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub header: SessionHeader,
+    pub messages: Vec<Message>,
+    pub path: PathBuf,
+}
+
+/// Summary shown by `sessions list`
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub updated_at: DateTime<Local>,
+    pub message_count: usize,
+    pub first_prompt: Option<String>,
+}
+
+/// Directory holding the sessions of the project rooted at `cwd`:
+/// `~/.code_agent/projects/<escaped cwd>/`
+pub fn project_dir(cwd: &Path) -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Could not determine home directory")?;
+    let cwd = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    Ok(home.join(".code_agent").join("projects").join(escape_path(&cwd)))
+}
+
+/// `path` as a single directory name: separators become `-` and any other
+/// character but letters, digits and `.` becomes `_` and its UTF-8 bytes
+/// in hex, so different paths never share a name
+fn escape_path(path: &Path) -> String {
+    let mut name = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '/' => name.push('-'),
+            c if c.is_ascii_alphanumeric() || c == '.' => name.push(c),
+            c => {
+                for byte in c.to_string().bytes() {
+                    name.push_str(&format!("_{:02x}", byte));
+                }
+            }
+        }
+    }
+    name
+}
+
+fn current_project_dir() -> Result<PathBuf> {
+    project_dir(&std::env::current_dir().context("Failed to get current directory")?)
+}
+
+/// Appends conversation changes to a session file as they happen
+pub struct SessionRecorder {
+    id: String,
+    file: File,
+    persisted: usize,
+}
+
+impl SessionRecorder {
+    /// Start a new session file in the directory of the project at `cwd`
+    pub fn create(model: &str, cwd: &Path) -> Result<Self> {
+        let dir = project_dir(cwd)?;
+        fs::create_dir_all(&dir)
+            .context(format!("Failed to create session directory: {}", dir.display()))?;
+
+        let now = Utc::now();
+        let id = format!(
+            "{}-{:04x}",
+            now.with_timezone(&Local).format("%Y%m%d-%H%M%S"),
+            rand::thread_rng().gen::<u16>()
+        );
+        let path = dir.join(format!("{}.jsonl", id));
+
+        let mut recorder = Self {
+            id: id.clone(),
+            file: open_append(&path)?,
+            persisted: 0,
+        };
+        recorder.write(&Record::Session(SessionHeader {
+            id,
+            created_at: now,
+            cwd: cwd.display().to_string(),
+            model: model.to_string(),
+        }))?;
+
+        Ok(recorder)
+    }
+
+    /// Continue appending to a previously saved session
+    pub fn resume(session: &Session) -> Result<Self> {
+        Ok(Self {
+            id: session.header.id.clone(),
+            file: open_append(&session.path)?,
+            persisted: session.messages.len(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Append messages that were added since the last call
+    pub fn sync(&mut self, messages: &[Message]) -> Result<()> {
+        if messages.len() < self.persisted {
+            return self.replace(messages);
+        }
+        for message in &messages[self.persisted..] {
+            self.write(&Record::Message {
+                timestamp: Utc::now(),
+                message: message.clone(),
+            })?;
+        }
+        self.persisted = messages.len();
+        Ok(())
+    }
+
+    /// Record that the history was rewritten as a whole
+    pub fn replace(&mut self, messages: &[Message]) -> Result<()> {
+        self.write(&Record::Replace {
+            timestamp: Utc::now(),
+            messages: messages.to_vec(),
+        })?;
+        self.persisted = messages.len();
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        writeln!(self.file, "{}", line).context("Failed to write session file")?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Failed to open session file: {}", path.display()))
+}
+
+/// Replay a session file
+pub fn load_file(path: &Path) -> Result<Session> {
+    let file = File::open(path).context(format!("Failed to open session: {}", path.display()))?;
+
+    let mut header = None;
+    let mut messages = Vec::new();
+
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A crash mid-write can leave a truncated last line; skip it
+        let Ok(record) = serde_json::from_str::<Record>(&line) else {
+            eprintln!(
+                "Warning: skipping unreadable line {} in {}",
+                line_number + 1,
+                path.display()
+            );
+            continue;
+        };
+        match record {
+            Record::Session(h) => header = Some(h),
+            Record::Message { message, .. } => messages.push(message),
+            Record::Replace { messages: m, .. } => messages = m,
+        }
+    }
+
+    Ok(Session {
+        header: header.context(format!("Session file has no header: {}", path.display()))?,
+        messages,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Find a session of the current project by id or unique id prefix
+pub fn find(id: &str) -> Result<PathBuf> {
+    let dir = current_project_dir()?;
+    let exact = dir.join(format!("{}.jsonl", id));
+    if exact.exists() {
+        return Ok(exact);
+    }
+
+    let matches: Vec<PathBuf> = session_files(&dir)?
+        .into_iter()
+        .filter(|path| file_id(path).starts_with(id))
+        .collect();
+
+    match matches.len() {
+        0 => anyhow::bail!("No session found matching '{}'", id),
+        1 => Ok(matches.into_iter().next().unwrap()),
+        n => anyhow::bail!("'{}' matches {} sessions; use a longer id", id, n),
+    }
+}
+
+pub fn load(id: &str) -> Result<Session> {
+    load_file(&find(id)?)
+}
+
+/// Most recently updated session of the current project
+pub fn latest() -> Result<Session> {
+    let path = session_files(&current_project_dir()?)?
+        .into_iter()
+        .max_by_key(|path| modified(path))
+        .context("No previous session found for this project")?;
+    load_file(&path)
+}
+
+/// Sessions of the current project, most recently updated first
+pub fn list() -> Result<Vec<SessionInfo>> {
+    let mut sessions: Vec<SessionInfo> = session_files(&current_project_dir()?)?
+        .into_iter()
+        .filter_map(|path| {
+            let session = load_file(&path).ok()?;
+            Some(SessionInfo {
+                id: session.header.id,
+                updated_at: modified(&path).into(),
+                message_count: session.messages.len(),
+                first_prompt: session
+                    .messages
+                    .iter()
+                    .find(|m| m.role == "user")
+                    .map(|m| m.content.clone()),
+            })
+        })
+        .collect();
+    sessions.sort_by_key(|info| std::cmp::Reverse(info.updated_at));
+    Ok(sessions)
+}
+
+pub fn delete(id: &str) -> Result<PathBuf> {
+    let path = find(id)?;
+    fs::remove_file(&path).context(format!("Failed to delete session: {}", path.display()))?;
+    Ok(path)
+}
+
+/// Render a session as Markdown for sharing, e.g. in a code review
+pub fn to_markdown(session: &Session) -> String {
+    let header = &session.header;
+    let mut out = format!(
+        "# Code Agent session {}\n\n- Started: {}\n- Directory: `{}`\n- Model: `{}`\n",
+        header.id,
+        header.created_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        header.cwd,
+        header.model
+    );
+
+    for message in &session.messages {
+        match message.role.as_str() {
+            "system" => {
+                out.push_str("\n## System\n\n");
+                out.push_str(&quote(&message.content));
+            }
+            "user" => {
+                out.push_str("\n## User\n\n");
+                out.push_str(&message.content);
+                out.push('\n');
+            }
+            "assistant" => {
+                out.push_str("\n## Assistant\n\n");
+                if !message.content.is_empty() {
+                    out.push_str(&message.content);
+                    out.push('\n');
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    let arguments = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                        .and_then(|v| serde_json::to_string_pretty(&v))
+                        .unwrap_or_else(|_| call.function.arguments.clone());
+                    out.push_str(&format!(
+                        "\n**Tool call:** `{}`\n\n```json\n{}\n```\n",
+                        call.function.name, arguments
+                    ));
+                }
+            }
+            "tool" => {
+                out.push_str(&format!(
+                    "\n<details><summary>Tool result</summary>\n\n{}\n{}\n{}\n\n</details>\n",
+                    fence(&message.content),
+                    message.content.trim_end(),
+                    fence(&message.content)
+                ));
+            }
+            other => {
+                out.push_str(&format!("\n## {}\n\n{}\n", other, message.content));
+            }
+        }
+    }
+
+    out
+}
+
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}\n", line))
+        .collect()
+}
+
+/// A code fence longer than any backtick run inside the content
+fn fence(content: &str) -> String {
+    let longest = content
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn session_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .collect())
+}
+
+fn file_id(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn modified(path: &Path) -> std::time::SystemTime {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .unwrap_or(std::time::UNIX_EPOCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::llm_client::{FunctionCall, ToolCall};
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            is_error: false,
+        }
+    }
+
+    #[test]
+    fn project_dirs_do_not_collide() {
+        let name = |path: &str| escape_path(Path::new(path));
+        assert_eq!(name("/home/me/crate"), "-home-me-crate");
+        assert_eq!(name("/a/b-c"), "-a-b_2dc");
+        assert_ne!(name("/a/b-c"), name("/a/b/c"));
+        assert_ne!(name("/a/b_c"), name("/a/b-c"));
+        assert_ne!(name("/a/b c"), name("/a/b_20c"));
+        assert_eq!(name("/src/été"), "-src-_c3_a9t_c3_a9");
+    }
+
+    #[test]
+    fn sessions_replay_appends_and_replacements_in_order() {
+        let path = std::env::temp_dir().join(format!("code_agent_session_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut recorder = SessionRecorder {
+            id: "s1".to_string(),
+            file: open_append(&path).unwrap(),
+            persisted: 0,
+        };
+        recorder
+            .write(&Record::Session(SessionHeader {
+                id: "s1".to_string(),
+                created_at: Utc::now(),
+                cwd: "/project".to_string(),
+                model: "gpt-4o".to_string(),
+            }))
+            .unwrap();
+
+        let mut messages = vec![message("system", "Be helpful"), message("user", "one")];
+        recorder.sync(&messages).unwrap();
+        messages.push(message("assistant", "first"));
+        recorder.sync(&messages).unwrap();
+        drop(recorder);
+
+        // Resume, then compact and carry on
+        let session = load_file(&path).unwrap();
+        assert_eq!(session.messages.len(), 3);
+        let mut recorder = SessionRecorder::resume(&session).unwrap();
+        let mut messages = session.messages;
+        messages.push(message("user", "two"));
+        recorder.sync(&messages).unwrap();
+        let mut messages = vec![messages[0].clone(), message("system", "summary"), message("user", "two")];
+        recorder.sync(&messages).unwrap();
+        messages.push(message("assistant", "second"));
+        recorder.sync(&messages).unwrap();
+        // A crash mid-write leaves a partial line
+        writeln!(recorder.file, "{{\"type\": \"message\", \"mess").unwrap();
+
+        let session = load_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(session.header.id, "s1");
+        let contents: Vec<_> = session.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Be helpful", "summary", "two", "second"]);
+    }
+
+    #[test]
+    fn markdown_fences_outgrow_backticks_in_the_content() {
+        assert_eq!(fence("plain"), "```");
+        assert_eq!(fence("```rust\nfn main() {}\n```"), "````");
+        assert_eq!(fence("a `` b ````` c"), "``````");
+
+        let session = Session {
+            header: SessionHeader {
+                id: "s1".to_string(),
+                created_at: Utc::now(),
+                cwd: "/project".to_string(),
+                model: "gpt-4o".to_string(),
+            },
+            messages: vec![
+                message("system", "Line one\nLine two"),
+                message("user", "Show the readme"),
+                Message {
+                    tool_calls: Some(vec![ToolCall {
+                        id: "1".to_string(),
+                        tool_type: "function".to_string(),
+                        function: FunctionCall {
+                            name: "read".to_string(),
+                            arguments: r#"{"file_path":"README.md"}"#.to_string(),
+                        },
+                    }]),
+                    ..message("assistant", "")
+                },
+                Message {
+                    tool_call_id: Some("1".to_string()),
+                    ..message("tool", "```bash\ncargo build\n```\n")
+                },
+            ],
+            path: PathBuf::from("s1.jsonl"),
+        };
+        let markdown = to_markdown(&session);
+        assert!(markdown.contains("## System\n\n> Line one\n> Line two\n"));
+        assert!(markdown.contains("**Tool call:** `read`\n\n```json\n{\n  \"file_path\": \"README.md\"\n}\n```"));
+        assert!(markdown.contains("\n````\n```bash\ncargo build\n```\n````\n"));
+    }
+}
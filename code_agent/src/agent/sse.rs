@@ -0,0 +1,114 @@
+/// A single server-sent event
+#[derive(Debug, Clone, Default)]
+pub struct SseEvent {
+    /// Concatenated `data:` lines
+    pub data: String,
+}
+
+/// Incremental parser for a `text/event-stream` body
+///
+/// Bytes are fed in as they arrive from the network; complete events are
+/// returned once their terminating blank line has been seen. Only whole
+/// lines are decoded, so a character split across chunks stays intact.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    current: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of the response body and return any completed events
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let bytes: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&bytes);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if let Some(event) = self.take_event() {
+                    events.push(event);
+                }
+                continue;
+            }
+
+            // Lines starting with ':' are comments (often used as keep-alives)
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+
+            if field == "data" {
+                if self.has_data {
+                    self.current.data.push('\n');
+                }
+                self.current.data.push_str(value);
+                self.has_data = true;
+            }
+        }
+
+        events
+    }
+
+    /// Flush a trailing event that was not followed by a blank line
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            let mut events = self.feed(&[rest.as_slice(), b"\n"].concat());
+            if let Some(event) = events.pop() {
+                return Some(event);
+            }
+        }
+        self.take_event()
+    }
+
+    fn take_event(&mut self) -> Option<SseEvent> {
+        if !self.has_data {
+            return None;
+        }
+        self.has_data = false;
+        Some(std::mem::take(&mut self.current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(events: Vec<SseEvent>) -> Vec<String> {
+        events.into_iter().map(|event| event.data).collect()
+    }
+
+    #[test]
+    fn events_split_across_chunks() {
+        let mut parser = SseParser::new();
+        let body = "data: {\"text\": \"héllo ✓\"}\n\n: keep-alive\n\ndata: [DONE]\n\n".as_bytes();
+        let mut events = Vec::new();
+        // One byte at a time splits every multi-byte character
+        for byte in body {
+            events.extend(data(parser.feed(std::slice::from_ref(byte))));
+        }
+        assert_eq!(events, ["{\"text\": \"héllo ✓\"}", "[DONE]"]);
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn crlf_multi_line_data_and_trailing_event() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"event: message\r\ndata: first\r\ndata:second\r\nid: 1\r\n\r\ndata: la");
+        assert_eq!(data(events), ["first\nsecond"]);
+        assert!(parser.feed(b"st").is_empty());
+        assert_eq!(parser.finish().map(|event| event.data).as_deref(), Some("last"));
+    }
+}
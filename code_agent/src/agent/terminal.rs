@@ -0,0 +1,162 @@
+use super::events::AgentEvent;
+use colored::*;
+use std::io::{self, Write};
+
+/// Prints agent events to the terminal. Streamed text is always shown;
+/// everything else only in verbose mode, where it traces the whole turn.
+pub struct TerminalRenderer {
+    verbose: bool,
+    /// A streamed line is still open and needs finishing
+    streaming: bool,
+    /// Text of the current response was printed while streamed
+    streamed_text: bool,
+    /// Tool calls of the current response were echoed while streamed
+    streamed_calls: bool,
+    /// Streamed tool call whose arguments are being printed
+    open_call: Option<usize>,
+}
+
+impl TerminalRenderer {
+    pub fn new(verbose: bool) -> Self {
+        Self {
+            verbose,
+            streaming: false,
+            streamed_text: false,
+            streamed_calls: false,
+            open_call: None,
+        }
+    }
+
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn render(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::TextDelta { text } => {
+                if !self.streamed_text && self.verbose {
+                    println!("{}", "Assistant:".bright_green().bold());
+                }
+                self.streaming = true;
+                self.streamed_text = true;
+                print!("{}", text);
+            }
+            AgentEvent::ToolCallStarted { index, name } => {
+                if !self.verbose {
+                    return;
+                }
+                if self.open_call.is_some() {
+                    println!("{}", ")".dimmed());
+                } else if self.streamed_text {
+                    println!();
+                }
+                self.streaming = true;
+                self.streamed_calls = true;
+                self.open_call = Some(*index);
+                print!("  {} {}", "→".bright_yellow(), format!("{}(", name).dimmed());
+            }
+            AgentEvent::ToolArgumentsDelta { index, arguments } => {
+                if self.verbose && self.open_call == Some(*index) {
+                    print!("{}", arguments.dimmed());
+                }
+            }
+            event => {
+                self.finish_stream();
+                self.render_complete(event);
+                return;
+            }
+        }
+        let _ = io::stdout().flush();
+    }
+
+    /// End the line a streamed response left open
+    fn finish_stream(&mut self) {
+        if !self.streaming {
+            return;
+        }
+        self.streaming = false;
+        if self.open_call.take().is_some() {
+            println!("{}", ")".dimmed());
+        } else if self.streamed_text {
+            println!();
+            if self.verbose {
+                println!();
+            }
+        }
+    }
+
+    fn render_complete(&mut self, event: &AgentEvent) {
+        if let AgentEvent::Iteration { .. } = event {
+            self.streamed_text = false;
+            self.streamed_calls = false;
+        }
+        // Shown even when not verbose, to explain the wait
+        if let AgentEvent::Retrying { message, .. } = event {
+            eprintln!("{}", message.dimmed());
+            return;
+        }
+        if let AgentEvent::Warning { message } = event {
+            eprintln!("{} {}", "Warning:".yellow(), message);
+            return;
+        }
+        if !self.verbose {
+            return;
+        }
+
+        match event {
+            AgentEvent::TurnStarted { prompt } => {
+                println!("{} {}", "User:".bright_cyan().bold(), prompt);
+                println!();
+            }
+            AgentEvent::Iteration { number, max } => {
+                println!("{} {}", "Iteration:".dimmed(), format!("{}/{}", number, max).dimmed());
+            }
+            AgentEvent::Compacted {
+                messages,
+                tokens_before,
+                tokens_after,
+            } => {
+                let message = format!(
+                    "Compacted {} message(s) into a summary (~{} → ~{} tokens)",
+                    messages, tokens_before, tokens_after
+                );
+                println!("{}", message.dimmed());
+            }
+            AgentEvent::Text { text } if !self.streamed_text => {
+                println!("{}", "Assistant:".bright_green().bold());
+                println!("{}", text);
+                println!();
+            }
+            AgentEvent::ToolCallsRequested { count } => {
+                println!("{} {} tool call(s)", "Assistant:".bright_green().bold(), count);
+            }
+            AgentEvent::ToolCall {
+                name, arguments, ..
+            } if !self.streamed_calls => {
+                println!(
+                    "  {} {}",
+                    "→".bright_yellow(),
+                    format!("{}({})", name, arguments).dimmed()
+                );
+            }
+            AgentEvent::ToolResult {
+                success: true,
+                output,
+                ..
+            } => {
+                println!(
+                    "  {} {}",
+                    "✓".bright_green(),
+                    output.lines().next().unwrap_or("Success")
+                );
+            }
+            AgentEvent::ToolResult { output, .. } => {
+                println!("  {} {}", "✗".bright_red(), output);
+            }
+            AgentEvent::Usage { summary, .. } => {
+                println!("{}", summary.dimmed());
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,249 +1,253 @@
+use crate::config::Config;
+use crate::mcp::{client::McpClient, tool::McpTool, ServerConfig};
 use crate::tools::*;
 use super::llm_client::{Tool as LlmTool, FunctionDefinition};
-use anyhow::Result;
-use serde_json::json;
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use colored::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::task::JoinSet;
 
 pub struct ToolRegistry {
-    tools: HashMap<String, Box<dyn Fn(ToolParams) -> Result<ToolResult> + Send + Sync>>,
+    tools: Vec<Box<dyn AsyncTool>>,
 }
 
 impl ToolRegistry {
-    pub fn new() -> Self {
-        let mut registry = Self {
-            tools: HashMap::new(),
-        };
+    /// The built-in tools; bash commands share one shell if
+    /// `persistent_shell` is set
+    pub fn new(persistent_shell: bool) -> Self {
+        let mut registry = Self { tools: Vec::new() };
 
         // Register all available tools
-        registry.register_tool("read", |params| {
-            file_ops::ReadTool.execute(params)
-        });
-        registry.register_tool("write", |params| {
-            file_ops::WriteTool.execute(params)
-        });
-        registry.register_tool("edit", |params| {
-            file_ops::EditTool.execute(params)
-        });
-        registry.register_tool("glob", |params| {
-            search::GlobTool.execute(params)
-        });
-        registry.register_tool("grep", |params| {
-            search::GrepTool.execute(params)
-        });
-        registry.register_tool("bash", |params| {
-            bash::BashTool.execute(params)
-        });
-        registry.register_tool("todo", |params| {
-            todo::TodoTool.execute(params)
-        });
+        registry.register(file_ops::ReadTool);
+        registry.register(file_ops::WriteTool);
+        registry.register(file_ops::EditTool);
+        registry.register(search::GlobTool);
+        registry.register(search::GrepTool);
+
+        let jobs = Arc::new(background::BackgroundJobs::default());
+        let bash = if persistent_shell {
+            bash::BashTool::persistent()
+        } else {
+            bash::BashTool::default()
+        };
+        registry.register(bash.with_background_jobs(Arc::clone(&jobs)));
+        registry.register(background::BashOutputTool { jobs: Arc::clone(&jobs) });
+        registry.register(background::BashStatusTool { jobs: Arc::clone(&jobs) });
+        registry.register(background::BashKillTool { jobs });
+
+        registry.register(todo::TodoTool);
+
+        registry
+    }
 
+    /// The built-in tools the config leaves on
+    pub fn from_config(config: &Config) -> Self {
+        let mut registry = Self::new(config.agent.persistent_shell);
+        registry.retain_enabled(config);
         registry
     }
 
-    fn register_tool<F>(&mut self, name: &str, func: F)
-    where
-        F: Fn(ToolParams) -> Result<ToolResult> + Send + Sync + 'static,
-    {
-        self.tools.insert(name.to_string(), Box::new(func));
+    /// Built-in tools plus those of the configured MCP servers, without the
+    /// tools the config switches off. Servers that fail to start are skipped
+    /// with a warning.
+    pub async fn load(config: &Config) -> Self {
+        let mut registry = Self::new(config.agent.persistent_shell);
+        let servers: BTreeMap<String, ServerConfig> = config
+            .mcp_servers
+            .iter()
+            .filter(|(name, _)| config.tool_enabled(&format!("mcp__{}", name)))
+            .map(|(name, server)| (name.clone(), server.clone()))
+            .collect();
+        for error in registry.register_mcp_servers(&servers).await {
+            eprintln!("{} {:#}", "Warning:".yellow(), error);
+        }
+        registry.retain_enabled(config);
+        registry
+    }
+
+    /// Drop the tools the config switches off
+    pub fn retain_enabled(&mut self, config: &Config) {
+        self.tools.retain(|tool| config.tool_enabled(tool.name()));
+    }
+
+    /// Start the given MCP servers concurrently and register their tools as
+    /// `mcp__<server>__<tool>`. Returns one error per server that could not
+    /// be used.
+    pub async fn register_mcp_servers(
+        &mut self,
+        servers: &BTreeMap<String, ServerConfig>,
+    ) -> Vec<anyhow::Error> {
+        let mut starting = JoinSet::new();
+        for (name, config) in servers {
+            let (name, config) = (name.clone(), config.clone());
+            starting.spawn(async move {
+                let client = McpClient::start(&name, &config).await?;
+                let tools = client
+                    .list_tools()
+                    .await
+                    .with_context(|| format!("Failed to list tools of MCP server '{}'", name))?;
+                anyhow::Ok((name, Arc::new(client), tools))
+            });
+        }
+
+        let mut started = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(joined) = starting.join_next().await {
+            match joined.map_err(anyhow::Error::from).and_then(|started| started) {
+                Ok(server) => started.push(server),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        // Register in server name order regardless of which started first
+        started.sort_by(|a, b| a.0.cmp(&b.0));
+        for (server, client, tools) in started {
+            for info in tools {
+                let tool = McpTool::new(&server, Arc::clone(&client), info);
+                if self.get(tool.name()).is_some() {
+                    errors.push(anyhow::anyhow!(
+                        "MCP server '{}' offers '{}' more than once; keeping the first",
+                        server,
+                        tool.name()
+                    ));
+                    continue;
+                }
+                self.tools.push(Box::new(tool));
+            }
+        }
+        errors
+    }
+
+    /// Register a synchronous tool, run through the `Blocking` adapter
+    fn register<T: Tool + 'static>(&mut self, tool: T) {
+        self.tools.push(Box::new(Blocking::new(tool)));
     }
 
-    pub fn execute_tool(&self, name: &str, params: ToolParams) -> Result<ToolResult> {
+    pub fn get(&self, name: &str) -> Option<&dyn AsyncTool> {
         self.tools
+            .iter()
+            .find(|tool| tool.name() == name)
+            .map(|tool| tool.as_ref())
+    }
+
+    /// Registered tools in registration order
+    pub fn tools(&self) -> impl Iterator<Item = &dyn AsyncTool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+
+    /// Execute a tool and cut its output to the context's output limit
+    pub async fn execute_tool(
+        &self,
+        name: &str,
+        params: ToolParams,
+        context: &ToolContext,
+    ) -> Result<ToolResult> {
+        let mut result = self
             .get(name)
             .ok_or_else(|| anyhow::anyhow!("Tool not found: {}", name))?
-            (params)
+            .execute(params, context)
+            .await?;
+        result.truncate(context.limits.max_output_bytes);
+        Ok(result)
     }
 
+    /// Function definitions sent to the model, generated from each tool's
+    /// params type
     pub fn get_tool_definitions(&self) -> Vec<LlmTool> {
-        vec![
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "read".to_string(),
-                    description: "Reads a file from the filesystem with optional line offset and limit".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "file_path": {
-                                "type": "string",
-                                "description": "The absolute or relative path to the file to read"
-                            },
-                            "offset": {
-                                "type": "integer",
-                                "description": "Optional line number to start reading from (0-indexed)"
-                            },
-                            "limit": {
-                                "type": "integer",
-                                "description": "Optional number of lines to read"
-                            }
-                        },
-                        "required": ["file_path"]
-                    }),
-                },
-            },
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "write".to_string(),
-                    description: "Writes content to a file, creating or overwriting as needed".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "file_path": {
-                                "type": "string",
-                                "description": "The path to the file to write"
-                            },
-                            "content": {
-                                "type": "string",
-                                "description": "The content to write to the file"
-                            }
-                        },
-                        "required": ["file_path", "content"]
-                    }),
-                },
-            },
-            LlmTool {
+        self.tools()
+            .map(|tool| LlmTool {
                 tool_type: "function".to_string(),
                 function: FunctionDefinition {
-                    name: "edit".to_string(),
-                    description: "Performs exact string replacements in files. The old_string must be unique unless replace_all is true.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "file_path": {
-                                "type": "string",
-                                "description": "The path to the file to edit"
-                            },
-                            "old_string": {
-                                "type": "string",
-                                "description": "The exact string to find and replace"
-                            },
-                            "new_string": {
-                                "type": "string",
-                                "description": "The replacement string"
-                            },
-                            "replace_all": {
-                                "type": "boolean",
-                                "description": "If true, replace all occurrences. Default is false."
-                            }
-                        },
-                        "required": ["file_path", "old_string", "new_string"]
-                    }),
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters_schema(),
                 },
-            },
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "glob".to_string(),
-                    description: "Fast file pattern matching using glob patterns like **/*.js or src/**/*.rs".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "pattern": {
-                                "type": "string",
-                                "description": "The glob pattern to match files against"
-                            },
-                            "path": {
-                                "type": "string",
-                                "description": "Optional directory to search in. Defaults to current directory."
-                            }
-                        },
-                        "required": ["pattern"]
-                    }),
-                },
-            },
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "grep".to_string(),
-                    description: "Search file contents using regex patterns. Supports filtering by file type and multiple output modes.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "pattern": {
-                                "type": "string",
-                                "description": "The regex pattern to search for"
-                            },
-                            "path": {
-                                "type": "string",
-                                "description": "Optional directory to search in. Defaults to current directory."
-                            },
-                            "glob": {
-                                "type": "string",
-                                "description": "Optional glob pattern to filter files (e.g., '*.rs', '*.txt')"
-                            },
-                            "case_insensitive": {
-                                "type": "boolean",
-                                "description": "If true, search is case-insensitive. Default is false."
-                            },
-                            "output_mode": {
-                                "type": "string",
-                                "description": "Output format: 'files_with_matches' (just filenames), 'content' (matching lines), or 'count' (match counts)",
-                                "enum": ["files_with_matches", "content", "count"]
-                            }
-                        },
-                        "required": ["pattern"]
-                    }),
-                },
-            },
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "bash".to_string(),
-                    description: "Executes bash commands with optional timeout. Use this to run shell commands, git operations, build tools, etc.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "command": {
-                                "type": "string",
-                                "description": "The bash command to execute"
-                            },
-                            "description": {
-                                "type": "string",
-                                "description": "Optional human-readable description of what the command does"
-                            },
-                            "timeout": {
-                                "type": "integer",
-                                "description": "Optional timeout in milliseconds. Default is 120000 (2 minutes)."
-                            }
-                        },
-                        "required": ["command"]
-                    }),
-                },
-            },
-            LlmTool {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "todo".to_string(),
-                    description: "Manages a task list for tracking progress. Can read current todos or write new ones.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "action": {
-                                "type": "string",
-                                "description": "Action to perform: 'read' or 'write'",
-                                "enum": ["read", "write"]
-                            },
-                            "todos": {
-                                "type": "array",
-                                "description": "Array of todo items (required for write action)",
-                                "items": {
-                                    "type": "object",
-                                    "properties": {
-                                        "content": {"type": "string"},
-                                        "status": {
-                                            "type": "string",
-                                            "enum": ["pending", "in_progress", "completed"]
-                                        },
-                                        "active_form": {"type": "string"}
-                                    },
-                                    "required": ["content", "status", "active_form"]
-                                }
-                            }
-                        }
-                    }),
-                },
-            },
-        ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::JsonSchema;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+
+    /// A value matching `schema`, using every property or only required ones
+    fn sample(schema: &Value, all_properties: bool) -> Value {
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            return options.last().cloned().expect("empty enum");
+        }
+        match schema.get("type").and_then(Value::as_str) {
+            Some("object") => {
+                let required: Vec<&str> = schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                let mut object = serde_json::Map::new();
+                for (name, property) in schema["properties"].as_object().into_iter().flatten() {
+                    if all_properties || required.contains(&name.as_str()) {
+                        object.insert(name.clone(), sample(property, all_properties));
+                    }
+                }
+                Value::Object(object)
+            }
+            Some("array") => json!([sample(&schema["items"], all_properties)]),
+            Some("string") => json!("sample"),
+            Some("integer") => json!(7),
+            Some("boolean") => json!(true),
+            other => panic!("unsupported schema type {:?} in {}", other, schema),
+        }
+    }
+
+    type Check = fn(&ToolRegistry, &str);
+
+    fn assert_round_trips<T: Serialize + DeserializeOwned + JsonSchema>(registry: &ToolRegistry, name: &str) {
+        let tool = registry
+            .get(name)
+            .unwrap_or_else(|| panic!("{} is not registered", name));
+        let schema = tool.parameters_schema();
+        assert_eq!(schema, parameters_schema::<T>(), "{} advertises a different schema", name);
+        assert_eq!(schema["type"], "object", "{} parameters must be an object", name);
+
+        let full = sample(&schema, true);
+        let params: T = serde_json::from_value(full.clone())
+            .unwrap_or_else(|e| panic!("{}: {} does not deserialize: {}", name, full, e));
+        assert_eq!(serde_json::to_value(&params).unwrap(), full, "{} does not round-trip", name);
+
+        let minimal = sample(&schema, false);
+        serde_json::from_value::<T>(minimal.clone())
+            .unwrap_or_else(|e| panic!("{}: {} does not deserialize: {}", name, minimal, e));
+    }
+
+    #[test]
+    fn every_tool_schema_round_trips_its_params() {
+        let registry = ToolRegistry::new(true);
+        let checks: &[(&str, Check)] = &[
+            ("read", assert_round_trips::<file_ops::ReadParams>),
+            ("write", assert_round_trips::<file_ops::WriteParams>),
+            ("edit", assert_round_trips::<file_ops::EditParams>),
+            ("glob", assert_round_trips::<search::GlobParams>),
+            ("grep", assert_round_trips::<search::GrepParams>),
+            ("bash", assert_round_trips::<bash::BashParams>),
+            ("bash_output", assert_round_trips::<background::JobParams>),
+            ("bash_status", assert_round_trips::<background::StatusParams>),
+            ("bash_kill", assert_round_trips::<background::JobParams>),
+            ("todo", assert_round_trips::<todo::TodoParams>),
+        ];
+
+        for tool in registry.tools() {
+            let (name, check) = checks
+                .iter()
+                .find(|(name, _)| *name == tool.name())
+                .unwrap_or_else(|| panic!("no round-trip check for tool {}", tool.name()));
+            check(&registry, name);
+        }
     }
 }
@@ -0,0 +1,193 @@
+use super::llm_client::Usage;
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// Price of prompt tokens read from the provider's prompt cache
+    pub cached_input: f64,
+}
+
+impl ModelPrice {
+    const fn new(input: f64, output: f64, cached_input: f64) -> Self {
+        Self {
+            input,
+            output,
+            cached_input,
+        }
+    }
+
+    /// Estimated cost in USD of the given usage
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let uncached = usage.prompt_tokens.saturating_sub(usage.cached_tokens);
+        (uncached as f64 * self.input
+            + usage.cached_tokens as f64 * self.cached_input
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Known prices, keyed by the `model_name`s in `litellm_config.yaml`.
+///
+/// Lookups also match names with a `-` suffix, so dated provider names such
+/// as `claude-3-5-sonnet-20241022` resolve to their family entry.
+const PRICES: &[(&str, ModelPrice)] = &[
+    ("gpt-4", ModelPrice::new(30.0, 60.0, 30.0)),
+    ("gpt-4-turbo", ModelPrice::new(10.0, 30.0, 10.0)),
+    ("gpt-4o", ModelPrice::new(2.5, 10.0, 1.25)),
+    ("gpt-4o-mini", ModelPrice::new(0.15, 0.6, 0.075)),
+    ("gpt-3.5-turbo", ModelPrice::new(0.5, 1.5, 0.5)),
+    ("claude-3-5-sonnet", ModelPrice::new(3.0, 15.0, 0.3)),
+    ("claude-3-opus", ModelPrice::new(15.0, 75.0, 1.5)),
+    ("claude-3-sonnet", ModelPrice::new(3.0, 15.0, 0.3)),
+    ("claude-3-haiku", ModelPrice::new(0.25, 1.25, 0.03)),
+];
+
+/// Look up the price of a model, preferring the longest matching name
+pub fn price_for(model: &str) -> Option<ModelPrice> {
+    PRICES
+        .iter()
+        .filter(|(name, _)| is_model(model, name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| *price)
+}
+
+/// Whether `model` is `name` or a variant of it such as `name-20240806`;
+/// `gpt-4o` is not a variant of `gpt-4`
+pub fn is_model(model: &str, name: &str) -> bool {
+    model
+        .strip_prefix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+/// Aggregates token usage for the current turn and the whole session
+#[derive(Debug, Clone)]
+pub struct UsageTracker {
+    model: String,
+    turn: Usage,
+    turn_requests: u32,
+    /// None once a request was made to a model without a known price
+    turn_cost: Option<f64>,
+    session: Usage,
+    session_requests: u32,
+    session_cost: Option<f64>,
+}
+
+impl UsageTracker {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            turn: Usage::default(),
+            turn_requests: 0,
+            turn_cost: Some(0.0),
+            session: Usage::default(),
+            session_requests: 0,
+            session_cost: Some(0.0),
+        }
+    }
+
+    /// Price later requests as made to `model`
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    /// Reset the per-turn counters at the start of a new user turn
+    pub fn start_turn(&mut self) {
+        self.turn = Usage::default();
+        self.turn_requests = 0;
+        self.turn_cost = Some(0.0);
+    }
+
+    /// Record one LLM request
+    pub fn record(&mut self, usage: Option<Usage>) {
+        let usage = usage.unwrap_or_default();
+        let cost = price_for(&self.model).map(|price| price.cost(&usage));
+        self.turn += usage;
+        self.turn_requests += 1;
+        self.turn_cost = self.turn_cost.zip(cost).map(|(total, cost)| total + cost);
+        self.session += usage;
+        self.session_requests += 1;
+        self.session_cost = self.session_cost.zip(cost).map(|(total, cost)| total + cost);
+    }
+
+    /// Tokens used by the current turn and the number of requests it made
+    pub fn turn(&self) -> (Usage, u32) {
+        (self.turn, self.turn_requests)
+    }
+
+    /// Estimated cost in USD of the current turn, if the price is known
+    pub fn turn_cost(&self) -> Option<f64> {
+        self.turn_cost
+    }
+
+    pub fn turn_summary(&self) -> String {
+        format!("Turn: {}", self.describe(&self.turn, self.turn_requests, self.turn_cost))
+    }
+
+    pub fn session_summary(&self) -> String {
+        format!(
+            "Session: {}",
+            self.describe(&self.session, self.session_requests, self.session_cost)
+        )
+    }
+
+    fn describe(&self, usage: &Usage, requests: u32, cost: Option<f64>) -> String {
+        let cost = match cost {
+            Some(cost) => format!("~${:.4}", cost),
+            None => format!("cost unknown for model '{}'", self.model),
+        };
+        format!(
+            "{} input ({} cached) + {} output = {} tokens in {} request(s), {}",
+            usage.prompt_tokens,
+            usage.cached_tokens,
+            usage.completion_tokens,
+            usage.total_tokens(),
+            requests,
+            cost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_match_whole_model_names() {
+        let input = |model: &str| price_for(model).map(|price| price.input);
+        assert_eq!(input("gpt-4"), Some(30.0));
+        assert_eq!(input("gpt-4-0613"), Some(30.0));
+        assert_eq!(input("gpt-4-turbo-2024-04-09"), Some(10.0));
+        assert_eq!(input("gpt-4o"), Some(2.5));
+        assert_eq!(input("gpt-4o-2024-08-06"), Some(2.5));
+        assert_eq!(input("gpt-4o-mini"), Some(0.15));
+        assert_eq!(input("claude-3-5-sonnet-20241022"), Some(3.0));
+        assert_eq!(input("gpt-4.1"), None);
+        assert_eq!(input("gpt-4omni"), None);
+    }
+
+    #[test]
+    fn costs_add_up_per_model() {
+        let usage = Usage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 100_000,
+            cached_tokens: 400_000,
+        };
+        let price = price_for("gpt-4o").unwrap();
+        assert!((price.cost(&usage) - (0.6 * 2.5 + 0.4 * 1.25 + 0.1 * 10.0)).abs() < 1e-9);
+
+        let mut tracker = UsageTracker::new("gpt-4o-mini");
+        tracker.record(Some(usage));
+        tracker.set_model("gpt-4o");
+        tracker.record(Some(usage));
+        let expected = price_for("gpt-4o-mini").unwrap().cost(&usage) + price.cost(&usage);
+        assert!((tracker.turn_cost().unwrap() - expected).abs() < 1e-9);
+
+        tracker.start_turn();
+        tracker.set_model("local-llama");
+        tracker.record(None);
+        assert_eq!(tracker.turn_cost(), None);
+        assert!(tracker.session_summary().contains("cost unknown for model 'local-llama'"));
+    }
+}
@@ -0,0 +1,383 @@
+use serde_json::Value;
+
+/// Tool-call arguments after parsing
+#[derive(Debug)]
+pub struct ParsedArguments {
+    pub value: Value,
+    /// The raw string was not valid JSON and had to be repaired
+    pub repaired: bool,
+}
+
+/// Parse the arguments string of a tool call, falling back to a lenient
+/// repair pass for the mistakes models commonly make
+pub fn parse_arguments(raw: &str) -> Result<ParsedArguments, String> {
+    // Some models send an empty string for tools without required parameters
+    if raw.trim().is_empty() {
+        return Ok(ParsedArguments {
+            value: Value::Object(Default::default()),
+            repaired: false,
+        });
+    }
+
+    let error = match serde_json::from_str(raw) {
+        Ok(value) => {
+            return Ok(ParsedArguments {
+                value,
+                repaired: false,
+            })
+        }
+        Err(e) => e,
+    };
+
+    match serde_json::from_str(&repair_json(raw)) {
+        Ok(value) => Ok(ParsedArguments {
+            value,
+            repaired: true,
+        }),
+        Err(_) => Err(format!("arguments are not valid JSON ({})", error)),
+    }
+}
+
+/// Best-effort fix-up of almost-JSON: strips code fences, converts
+/// single-quoted strings, unquoted keys, Python literals and trailing
+/// commas, and closes strings, arrays and objects left open by a truncated
+/// response
+pub fn repair_json(raw: &str) -> String {
+    let mut input = raw.trim();
+    if let Some(rest) = input.strip_prefix("```") {
+        // Drop the opening fence with its language tag and the closing fence
+        input = rest.split_once('\n').map_or("", |(_, body)| body);
+        input = input.trim_end().trim_end_matches("```").trim();
+    }
+
+    let mut out = String::with_capacity(input.len() + 8);
+    // Open brackets, innermost last
+    let mut stack = Vec::new();
+    // Quote character of the string being copied, if inside one
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            match c {
+                '\\' => match chars.next() {
+                    // `\'` is not a valid JSON escape
+                    Some('\'') => out.push('\''),
+                    Some(escaped) => {
+                        out.push('\\');
+                        out.push(escaped);
+                    }
+                    None => {}
+                },
+                '"' if q == '\'' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                c if c == q => {
+                    out.push('"');
+                    quote = None;
+                }
+                c => out.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                out.push('"');
+                quote = Some(c);
+            }
+            '{' | '[' => {
+                stack.push(c);
+                out.push(c);
+            }
+            '}' | ']' => {
+                strip_trailing_comma(&mut out);
+                stack.pop();
+                out.push(c);
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut word = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if !next.is_ascii_alphanumeric() && next != '_' {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "True" | "true" => out.push_str("true"),
+                    "False" | "false" => out.push_str("false"),
+                    "None" | "null" => out.push_str("null"),
+                    // Unquoted object key
+                    _ if chars.clone().find(|c| !c.is_whitespace()) == Some(':') => {
+                        out.push('"');
+                        out.push_str(&word);
+                        out.push('"');
+                    }
+                    _ => out.push_str(&word),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    // Close whatever a truncated response left open
+    if quote.is_some() {
+        // A dangling escape would swallow the closing quote
+        if out.ends_with('\\') && !out.ends_with("\\\\") {
+            out.pop();
+        }
+        out.push('"');
+    }
+    while let Some(open) = stack.pop() {
+        strip_trailing_comma(&mut out);
+        if out.trim_end().ends_with(':') {
+            out.push_str(" null");
+        }
+        out.push(if open == '{' { '}' } else { ']' });
+    }
+    out
+}
+
+fn strip_trailing_comma(out: &mut String) {
+    let trimmed = out.trim_end().len();
+    if out[..trimmed].ends_with(',') {
+        out.truncate(trimmed - 1);
+    }
+}
+
+/// Check `value` against the subset of JSON Schema used by tool parameter
+/// schemas: `type`, `enum`, `required`, `properties`, `items` and `minimum`.
+/// Returns one message per problem.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let location = if path.is_empty() {
+        "arguments".to_string()
+    } else {
+        format!("`{}`", path)
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{} must be {}, got {}",
+                location,
+                types.join(" or "),
+                describe(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            let options: Vec<String> = options.iter().map(Value::to_string).collect();
+            errors.push(format!(
+                "{} must be one of {}, got {}",
+                location,
+                options.join(", "),
+                value
+            ));
+        }
+    }
+
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        value.as_f64(),
+    ) {
+        if number < minimum {
+            errors.push(format!("{} must be at least {}, got {}", location, minimum, number));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        for field in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if object.get(field).is_none_or(Value::is_null) {
+                errors.push(format!("missing required field `{}`", join(path, field)));
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (name, property) in properties {
+                match object.get(name) {
+                    // Optional fields may be sent as null
+                    None | Some(Value::Null) => {}
+                    Some(field) => validate_at(property, field, &join(path, name), errors),
+                }
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate_at(item_schema, item, &format!("{}[{}]", path, i), errors);
+        }
+    }
+}
+
+/// Compact reminder of a tool's parameters, e.g.
+/// `read(file_path: string, limit?: integer, offset?: integer)`
+pub fn signature(tool_name: &str, schema: &Value) -> String {
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let parameters: Vec<String> = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .map(|(name, property)| {
+            let optional = if required.contains(&name.as_str()) { "" } else { "?" };
+            let kind = match property.get("enum").and_then(Value::as_array) {
+                Some(options) => options
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+                None => property
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or("any")
+                    .to_string(),
+            };
+            format!("{}{}: {}", name, optional, kind)
+        })
+        .collect();
+    format!("{}({})", tool_name, parameters.join(", "))
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("boolean {}", b),
+        Value::Number(n) => format!("number {}", n),
+        Value::String(s) if s.chars().count() > 40 => "a string".to_string(),
+        Value::String(s) => format!("string {:?}", s),
+        Value::Array(_) => "an array".to_string(),
+        Value::Object(_) => "an object".to_string(),
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn repaired(raw: &str) -> Value {
+        serde_json::from_str(&repair_json(raw)).unwrap()
+    }
+
+    #[test]
+    fn repairs_common_mistakes() {
+        assert_eq!(repaired("```json\n{\"a\": 1}\n```"), json!({"a": 1}));
+        assert_eq!(repaired("```\n[1, 2]\n```"), json!([1, 2]));
+        assert_eq!(
+            repaired("{'path': 'it\\'s \"here\"'}"),
+            json!({"path": "it's \"here\""})
+        );
+        assert_eq!(repaired("{a: [1, 2,], b: True,}"), json!({"a": [1, 2], "b": true}));
+        assert_eq!(repaired("{\"x\": None}"), json!({"x": null}));
+        assert_eq!(repaired("{\"text\": \"two\nlines\"}"), json!({"text": "two\nlines"}));
+    }
+
+    #[test]
+    fn closes_truncated_json() {
+        assert_eq!(
+            repaired("{\"file_path\": \"a.rs\", \"content\": \"fn main() {"),
+            json!({"file_path": "a.rs", "content": "fn main() {"})
+        );
+        // A dangling escape is dropped
+        assert_eq!(repaired("{\"content\": \"a\\"), json!({"content": "a"}));
+        assert_eq!(
+            repaired("{\"edits\": [{\"old\": \"x\", \"new\": {\"deep\": [1,"),
+            json!({"edits": [{"old": "x", "new": {"deep": [1]}}]})
+        );
+        assert_eq!(repaired("{\"a\": {\"b\":"), json!({"a": {"b": null}}));
+    }
+
+    #[test]
+    fn parse_reports_whether_it_repaired() {
+        let parsed = parse_arguments("{\"a\": 1}").unwrap();
+        assert!(!parsed.repaired);
+        assert_eq!(parse_arguments("  ").unwrap().value, json!({}));
+        let parsed = parse_arguments("{'a': 1").unwrap();
+        assert!(parsed.repaired);
+        assert_eq!(parsed.value, json!({"a": 1}));
+        assert!(parse_arguments("{\"a\" 1}").is_err());
+    }
+
+    #[test]
+    fn validates_against_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "file_path": {"type": "string"},
+                "limit": {"type": "integer", "minimum": 1},
+                "mode": {"enum": ["a", "b"]},
+                "paths": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["file_path"]
+        });
+        assert!(validate(&schema, &json!({"file_path": "a", "limit": null})).is_empty());
+        // Fields the schema does not mention are ignored
+        assert!(validate(&schema, &json!({"file_path": "a", "extra": 1})).is_empty());
+        assert_eq!(
+            validate(&schema, &json!({"limit": 0})),
+            ["missing required field `file_path`", "`limit` must be at least 1, got 0"]
+        );
+        assert_eq!(
+            validate(&schema, &json!({"file_path": 3, "limit": 1.5, "mode": "c", "paths": ["x", 2]})),
+            [
+                "`file_path` must be string, got number 3",
+                "`limit` must be integer, got number 1.5",
+                "`mode` must be one of \"a\", \"b\", got \"c\"",
+                "`paths[1]` must be string, got number 2",
+            ]
+        );
+        assert_eq!(validate(&schema, &json!([])), ["arguments must be object, got an array"]);
+        assert_eq!(
+            signature("read", &schema),
+            "read(file_path: string, limit?: integer, mode?: \"a\" | \"b\", paths?: array)"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Custom commands: markdown prompt files in `.code_agent/commands/` (the
+//! project's) and `~/.code_agent/commands/` (the user's), run as REPL
+//! slash commands or with `code_agent run <name>`.
+//!
+//! ```markdown
+//! ---
+//! description: Review the staged diff
+//! allowed-tools: read, grep, bash(git diff*)
+//! model: claude-3-5-sonnet
+//! ---
+//! Review this diff, focusing on $ARGUMENTS:
+//!
+//! !`git diff --staged`
+//! ```
+
+use crate::agent::permissions::{Decision, PermissionPolicy};
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Placeholder replaced by the command's arguments
+const ARGUMENTS: &str = "$ARGUMENTS";
+
+/// Longest output of an embedded shell command; the rest is cut off
+const MAX_SHELL_OUTPUT: usize = 32 * 1024;
+
+/// How long an embedded shell command may run
+const SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A prompt template loaded from a markdown file
+#[derive(Debug, Clone)]
+pub struct CustomCommand {
+    pub name: String,
+    pub path: PathBuf,
+    /// From the front-matter, or else the first line of the prompt
+    pub description: String,
+    /// What to pass as arguments, shown by `/help`
+    pub argument_hint: Option<String>,
+    /// Permission rules naming the only tools the command may use
+    pub allowed_tools: Option<Vec<String>>,
+    pub model: Option<String>,
+    template: String,
+}
+
+impl CustomCommand {
+    /// Parse `text`, the contents of the command file at `path`
+    pub fn parse(name: &str, path: &Path, text: &str) -> Result<Self> {
+        let mut command = Self {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            description: String::new(),
+            argument_hint: None,
+            allowed_tools: None,
+            model: None,
+            template: text.to_string(),
+        };
+
+        let front_matter = text
+            .strip_prefix("---\n")
+            .and_then(|rest| rest.split_once("\n---\n").or_else(|| rest.split_once("\n---")));
+        if let Some((front_matter, template)) = front_matter {
+            command.template = template.trim_start_matches('\n').to_string();
+            for line in front_matter.lines().filter(|line| !line.trim().is_empty()) {
+                let (key, value) = line
+                    .split_once(':')
+                    .context(format!("Expected 'key: value' in the front-matter, got '{}'", line))?;
+                let value = unquote(value.trim());
+                match key.trim() {
+                    "description" => command.description = value.to_string(),
+                    "argument-hint" => command.argument_hint = Some(value.to_string()),
+                    "allowed-tools" => command.allowed_tools = Some(split_rules(value)),
+                    "model" => command.model = Some(value.to_string()),
+                    key => bail!(
+                        "Unknown front-matter key '{}'; expected description, argument-hint, \
+                         allowed-tools or model",
+                        key
+                    ),
+                }
+            }
+        }
+
+        if command.description.is_empty() {
+            command.description = command
+                .template
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .trim_start_matches('#')
+                .trim()
+                .chars()
+                .take(60)
+                .collect();
+        }
+        Ok(command)
+    }
+
+    /// The prompt to send: the output of every !`command` embedded, then
+    /// `$ARGUMENTS` replaced by `arguments`. Without the placeholder,
+    /// arguments are added on a line of their own.
+    ///
+    /// Commands are checked like the model's bash calls, so `permissions`
+    /// should already grant the command's `allowed-tools`; one that is
+    /// denied, or that the user rejects, fails the whole prompt.
+    pub async fn prompt(
+        &self,
+        arguments: &str,
+        cwd: &Path,
+        permissions: &mut PermissionPolicy,
+    ) -> Result<String> {
+        let shell = Regex::new(r"!`([^`]+)`").unwrap();
+        let mut prompt = String::new();
+        let mut last = 0;
+        for captures in shell.captures_iter(&self.template) {
+            let whole = captures.get(0).unwrap();
+            let command = &captures[1];
+            if let Decision::Deny(reason) =
+                permissions
+                    .check("bash", &json!({ "command": command }), &CancellationToken::new())
+                    .await
+            {
+                bail!("Not running `{}` from {}: {}", command, self.path.display(), reason);
+            }
+            prompt.push_str(&self.template[last..whole.start()]);
+            prompt.push_str(&run_shell(command, cwd).await?);
+            last = whole.end();
+        }
+        prompt.push_str(&self.template[last..]);
+
+        if prompt.contains(ARGUMENTS) {
+            prompt = prompt.replace(ARGUMENTS, arguments);
+        } else if !arguments.is_empty() {
+            prompt = format!("{}\n\n{}", prompt.trim_end(), arguments);
+        }
+        Ok(prompt.trim().to_string())
+    }
+}
+
+/// Command directories, lowest precedence first
+pub fn dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".code_agent").join("commands"));
+    }
+    dirs.push(PathBuf::from(".code_agent").join("commands"));
+    dirs
+}
+
+/// Every custom command by name; a project command replaces a user
+/// command of the same name. Files that cannot be read or parsed are
+/// skipped with a warning.
+pub fn load() -> Vec<CustomCommand> {
+    load_from(&dirs())
+}
+
+fn load_from(dirs: &[PathBuf]) -> Vec<CustomCommand> {
+    let mut commands = BTreeMap::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "md") || !path.is_file() {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            // Could not be typed as a command
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                continue;
+            }
+            let command = fs::read_to_string(&path)
+                .context("Failed to read the file")
+                .and_then(|text| CustomCommand::parse(name, &path, &text));
+            match command {
+                Ok(command) => {
+                    commands.insert(name.to_string(), command);
+                }
+                Err(e) => eprintln!("Warning: skipping command file {}: {:#}", path.display(), e),
+            }
+        }
+    }
+    commands.into_values().collect()
+}
+
+/// The custom command called `name`
+pub fn find(name: &str) -> Result<CustomCommand> {
+    load()
+        .into_iter()
+        .find(|command| command.name == name)
+        .context(format!(
+            "No custom command '{}' in {}",
+            name,
+            dirs().iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>().join(" or ")
+        ))
+}
+
+/// Run `command` in `cwd` and return what it printed. Failures are part
+/// of the output, so the model sees them.
+async fn run_shell(command: &str, cwd: &Path) -> Result<String> {
+    let child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(SHELL_TIMEOUT, child).await {
+        Ok(output) => output.context(format!("Failed to run `{}`", command))?,
+        Err(_) => bail!("`{}` did not finish within {}s", command, SHELL_TIMEOUT.as_secs()),
+    };
+
+    let mut text = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            text.push_str(&format!("\n{}", stderr.trim_end()));
+        }
+        text.push_str(&format!("\n({})", output.status));
+    }
+    if text.len() > MAX_SHELL_OUTPUT {
+        let mut end = MAX_SHELL_OUTPUT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[truncated]");
+    }
+    Ok(text.trim_start_matches('\n').to_string())
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+}
+
+/// `read, bash(git diff*)` or `[read, bash(git diff*)]` as rules; commas
+/// inside a rule's parentheses do not split it
+fn split_rules(value: &str) -> Vec<String> {
+    let value = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+    let mut rules = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                rules.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    rules.push(current);
+    rules
+        .iter()
+        .map(|rule| unquote(rule.trim()).to_string())
+        .filter(|rule| !rule.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::permissions::{PermissionsConfig, Rule};
+
+    #[tokio::test]
+    async fn front_matter_arguments_and_shell_output() {
+        let text = "---\ndescription: Review the diff\nallowed-tools: [read, \"bash(git diff {a,b}*)\"]\n\
+                    model: gpt-4o\n---\n\nFocus on $ARGUMENTS.\n\n!`echo staged; echo oops >&2; exit 3`\n";
+        let command = CustomCommand::parse("review", Path::new("review.md"), text).unwrap();
+        assert_eq!(command.description, "Review the diff");
+        assert_eq!(
+            command.allowed_tools.clone().unwrap_or_default(),
+            ["read", "bash(git diff {a,b}*)"]
+        );
+        assert_eq!(command.model.as_deref(), Some("gpt-4o"));
+
+        let mut permissions = PermissionPolicy::default();
+        permissions
+            .merge(&PermissionsConfig {
+                allow: vec!["bash(echo *)".to_string(), "bash(exit *)".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        let prompt = command.prompt("naming", Path::new("."), &mut permissions).await.unwrap();
+        assert_eq!(prompt, "Focus on naming.\n\nstaged\noops\n(exit status: 3)");
+
+        let text = "# Write a changelog entry\n";
+        let plain = CustomCommand::parse("changelog", Path::new("changelog.md"), text).unwrap();
+        assert_eq!(plain.description, "Write a changelog entry");
+        assert!(plain.allowed_tools.is_none());
+        assert_eq!(
+            plain.prompt("for 1.2", Path::new("."), &mut permissions).await.unwrap(),
+            "# Write a changelog entry\n\nfor 1.2"
+        );
+
+        assert!(CustomCommand::parse("x", Path::new("x.md"), "---\ntools: read\n---\nHi").is_err());
+    }
+
+    #[tokio::test]
+    async fn embedded_commands_need_permission() {
+        let text = "Status:\n!`git status --short; touch pwned`\n";
+        let command = CustomCommand::parse("status", Path::new("status.md"), text).unwrap();
+        // Not interactive, so anything that would be asked about is refused
+        let mut permissions = PermissionPolicy::default();
+        let error = command.prompt("", Path::new("."), &mut permissions).await.unwrap_err();
+        assert!(error.to_string().contains("requires approval"), "{}", error);
+
+        // The command's own allowed-tools cover only part of it
+        permissions.set_granted(vec![Rule::parse("bash(git status*)").unwrap()]);
+        assert!(command.prompt("", Path::new("."), &mut permissions).await.is_err());
+        permissions.set_granted(vec![
+            Rule::parse("bash(git status*)").unwrap(),
+            Rule::parse("bash(touch pwned)").unwrap(),
+        ]);
+        permissions
+            .merge(&PermissionsConfig {
+                deny: vec!["bash(touch *)".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        let error = command.prompt("", Path::new("."), &mut permissions).await.unwrap_err();
+        assert!(error.to_string().contains("deny rule"), "{}", error);
+    }
+
+    #[test]
+    fn unreadable_command_files_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("code_agent_commands_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("good.md"), "Say hi").unwrap();
+        fs::write(dir.join("bad.md"), "---\nunknown: key\n---\nHi").unwrap();
+        fs::write(dir.join("binary.md"), [0xff, 0xfe]).unwrap();
+        let commands = load_from(std::slice::from_ref(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = commands.iter().map(|command| command.name.as_str()).collect();
+        assert_eq!(names, ["good"]);
+    }
+}
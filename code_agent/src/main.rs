@@ -1,10 +1,22 @@
 mod tools;
 mod agent;
+mod config;
+mod custom_commands;
+mod mcp;
+mod output;
+mod repl;
+mod server;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use colored::*;
 use serde_json::json;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use agent::tool_registry::ToolRegistry;
+use config::{Config, ConfigLayer};
+use output::OutputFormat;
 use tools::*;
 
 #[derive(Parser)]
@@ -13,6 +25,9 @@ use tools::*;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Override a config setting, e.g. llm.model=gpt-4o (repeatable)
+    #[arg(long = "config", value_name = "KEY=VALUE")]
+    settings: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -74,6 +89,15 @@ enum Commands {
         /// Output mode: files_with_matches, content, or count
         #[arg(short, long, default_value = "files_with_matches")]
         output_mode: String,
+        /// Lines of context before each match (content mode)
+        #[arg(short = 'B', long, default_value_t = 0)]
+        before: usize,
+        /// Lines of context after each match (content mode)
+        #[arg(short = 'A', long, default_value_t = 0)]
+        after: usize,
+        /// Lines of context before and after each match (content mode)
+        #[arg(short = 'C', long)]
+        context: Option<usize>,
     },
     /// Execute a bash command
     Bash {
@@ -82,6 +106,9 @@ enum Commands {
         /// Description of what the command does
         #[arg(short, long)]
         description: Option<String>,
+        /// Timeout in milliseconds (default 120000, maximum 600000)
+        #[arg(short, long)]
+        timeout: Option<u64>,
     },
     /// Manage todos
     Todo {
@@ -102,9 +129,129 @@ enum Commands {
         /// System prompt to guide agent behavior
         #[arg(short, long)]
         system: Option<String>,
+        /// Model to use instead of the configured one
+        #[arg(short, long)]
+        model: Option<String>,
+        /// Stream the response token by token as it is generated
+        #[arg(long)]
+        stream: bool,
+        /// Resume a saved session by id (or unique id prefix)
+        #[arg(short, long, conflicts_with_all = ["continue_session", "system"])]
+        resume: Option<String>,
+        /// Continue the most recent session of this project
+        #[arg(short = 'c', long = "continue", conflicts_with = "system")]
+        continue_session: bool,
+        /// Allow tool calls matching a rule without asking, e.g. "bash(cargo test*)"
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+        /// Deny tool calls matching a rule, e.g. "write(.env)"
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<String>,
+        /// Output of a single task: the answer as text, one JSON object, or
+        /// one JSON event per line
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "prompt")]
+        output_format: OutputFormat,
+    },
+    /// Run a custom command from .code_agent/commands/ or
+    /// ~/.code_agent/commands/ as a single task
+    Run {
+        /// Name of the command (its file name without .md); lists the
+        /// commands if left out
+        name: Option<String>,
+        /// Arguments, substituted for $ARGUMENTS (after `--` if they
+        /// start with a dash)
+        arguments: Vec<String>,
+        /// Enable verbose output showing tool calls
+        #[arg(short, long)]
+        verbose: bool,
+        /// Stream the response token by token as it is generated
+        #[arg(long)]
+        stream: bool,
+        /// Allow tool calls matching a rule without asking, e.g. "bash(cargo test*)"
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+        /// Deny tool calls matching a rule, e.g. "write(.env)"
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<String>,
+        /// Output of the task: the answer as text, one JSON object, or one
+        /// JSON event per line
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output_format: OutputFormat,
+    },
+    /// Manage saved agent sessions of the current project
+    Sessions {
+        #[command(subcommand)]
+        action: SessionsAction,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
     },
     /// List all available tools
     List,
+    /// Serve an HTTP API for running agent sessions remotely
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Allow tool calls matching a rule, e.g. "bash(cargo test*)"
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+        /// Deny tool calls matching a rule, e.g. "write(.env)"
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<String>,
+    },
+    /// Serve the built-in tools to other agents over MCP (stdio)
+    McpServe {
+        /// Allow tool calls matching a rule, e.g. "bash(cargo test*)"
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+        /// Deny tool calls matching a rule, e.g. "write(.env)"
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsAction {
+    /// List saved sessions, most recent first
+    List,
+    /// Print the transcript of a session
+    Show {
+        /// Session id (or unique id prefix)
+        id: String,
+    },
+    /// Resume a session in interactive mode
+    Resume {
+        /// Session id (or unique id prefix)
+        id: String,
+        /// Enable verbose output showing tool calls
+        #[arg(short, long)]
+        verbose: bool,
+        /// Stream the response token by token as it is generated
+        #[arg(long)]
+        stream: bool,
+    },
+    /// Delete a session
+    Delete {
+        /// Session id (or unique id prefix)
+        id: String,
+    },
+    /// Export a session as Markdown
+    Export {
+        /// Session id (or unique id prefix)
+        id: String,
+        /// File to write to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective configuration and where each value came from
+    Show,
 }
 
 #[tokio::main]
@@ -112,6 +259,11 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok(); // Load .env file if present
 
     let cli = Cli::parse();
+    let mut overrides = cli
+        .settings
+        .iter()
+        .map(|setting| ConfigLayer::parse_override(setting))
+        .collect::<Result<Vec<_>>>()?;
 
     let result = match cli.command {
         Commands::Read {
@@ -172,6 +324,9 @@ async fn main() -> Result<()> {
             glob,
             case_insensitive,
             output_mode,
+            before,
+            after,
+            context,
         } => {
             let tool = search::GrepTool;
             let params = ToolParams {
@@ -181,6 +336,8 @@ async fn main() -> Result<()> {
                     "glob": glob,
                     "case_insensitive": case_insensitive,
                     "output_mode": output_mode,
+                    "context_before": context.unwrap_or(before),
+                    "context_after": context.unwrap_or(after),
                 }),
             };
             tool.execute(params)?
@@ -188,14 +345,17 @@ async fn main() -> Result<()> {
         Commands::Bash {
             command,
             description,
+            timeout,
         } => {
-            let tool = bash::BashTool;
-            let params = ToolParams {
-                data: json!({
-                    "command": command,
-                    "description": description,
-                }),
-            };
+            let tool = bash::BashTool::default();
+            let mut data = json!({
+                "command": command,
+                "description": description,
+            });
+            if let Some(timeout) = timeout {
+                data["timeout"] = json!(timeout);
+            }
+            let params = ToolParams { data };
             tool.execute(params)?
         }
         Commands::Todo { action, json } => {
@@ -220,19 +380,111 @@ async fn main() -> Result<()> {
             prompt,
             verbose,
             system,
+            model,
+            stream,
+            resume,
+            continue_session,
+            allow,
+            deny,
+            output_format,
         } => {
-            return run_agent(prompt, verbose, system).await;
+            let resume = if let Some(id) = resume {
+                Some(agent::session::load(&id)?)
+            } else if continue_session {
+                Some(agent::session::latest()?)
+            } else {
+                None
+            };
+            if let Some(model) = model {
+                let mut layer = ConfigLayer::default();
+                layer.llm.model = Some(model);
+                overrides.push(layer);
+            }
+            overrides.push(permission_rules(allow, deny));
+            let options = AgentOptions {
+                verbose,
+                system,
+                stream,
+                resume,
+                overrides,
+                output_format,
+                allowed_tools: None,
+            };
+            return run_agent(prompt, options).await;
+        }
+        Commands::Run {
+            name,
+            arguments,
+            verbose,
+            stream,
+            allow,
+            deny,
+            output_format,
+        } => {
+            let Some(name) = name else {
+                print_custom_commands()?;
+                return Ok(());
+            };
+            let command = custom_commands::find(&name)?;
+            let cwd = std::env::current_dir().context("Failed to get current directory")?;
+            overrides.push(permission_rules(allow, deny));
+
+            // Embedded commands run before the agent starts, so the user can
+            // still be asked about them
+            let config = Config::load(overrides.clone())?;
+            let mut permissions = agent::permissions::PermissionPolicy::from_config(&config)?;
+            let granted = command.allowed_tools.iter().flatten();
+            permissions.set_granted(
+                granted.map(|rule| agent::permissions::Rule::parse(rule)).collect::<Result<_>>()?,
+            );
+            permissions.set_interactive(std::io::stdin().is_terminal());
+            let prompt = command.prompt(&arguments.join(" "), &cwd, &mut permissions).await?;
+
+            if let Some(model) = command.model {
+                // The command line still takes precedence
+                let mut layer = ConfigLayer::default();
+                layer.llm.model = Some(model);
+                overrides.insert(0, layer);
+            }
+            let options = AgentOptions {
+                verbose,
+                stream,
+                overrides,
+                output_format,
+                allowed_tools: command.allowed_tools,
+                ..Default::default()
+            };
+            return run_agent(Some(prompt), options).await;
+        }
+        Commands::Sessions { action } => {
+            return run_sessions(action, overrides).await;
+        }
+        Commands::Serve { listen, allow, deny } => {
+            overrides.push(permission_rules(allow, deny));
+            return server::serve(&listen, Config::load(overrides)?).await;
+        }
+        Commands::McpServe { allow, deny } => {
+            overrides.push(permission_rules(allow, deny));
+            let config = Config::load(overrides)?;
+            // Only the built-in tools: serving the tools of configured MCP
+            // servers could start this server again
+            let registry = ToolRegistry::from_config(&config);
+            let permissions = agent::permissions::PermissionPolicy::from_config(&config)?;
+            let server = mcp::server::McpServer::new(registry, permissions);
+            return server.serve().await;
+        }
+        Commands::Config { action } => {
+            let ConfigAction::Show = action;
+            show_config(&Config::load(overrides)?);
+            return Ok(());
         }
         Commands::List => {
+            let config = Config::load(overrides)?;
             println!("{}", "Available Tools:".bright_cyan().bold());
             println!();
-            list_tool(&file_ops::ReadTool);
-            list_tool(&file_ops::WriteTool);
-            list_tool(&file_ops::EditTool);
-            list_tool(&search::GlobTool);
-            list_tool(&search::GrepTool);
-            list_tool(&bash::BashTool);
-            list_tool(&todo::TodoTool);
+            for tool in ToolRegistry::load(&config).await.tools() {
+                list_tool(tool);
+            }
             return Ok(());
         }
     };
@@ -251,93 +503,202 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn run_agent(
-    prompt: Option<String>,
+/// Settings for an agent run collected from the command line
+#[derive(Default)]
+struct AgentOptions {
     verbose: bool,
     system: Option<String>,
-) -> Result<()> {
-    let mut agent = agent::AgentLoop::new(verbose)?;
+    stream: bool,
+    resume: Option<agent::session::Session>,
+    /// Config layers from the command line
+    overrides: Vec<ConfigLayer>,
+    output_format: OutputFormat,
+    /// Rules of a custom command limiting the tools
+    allowed_tools: Option<Vec<String>>,
+}
 
-    // Set system prompt
-    let default_system = "You are a helpful coding assistant with access to file operations, \
-        search tools, and bash commands. Use these tools to help the user accomplish their tasks. \
-        Be concise and efficient in your tool usage.".to_string();
+/// A config layer with the `--allow`/`--deny` rules of the command line
+fn permission_rules(allow: Vec<String>, deny: Vec<String>) -> ConfigLayer {
+    let mut layer = ConfigLayer::default();
+    layer.permissions.allow = allow;
+    layer.permissions.deny = deny;
+    layer
+}
 
-    agent.set_system_prompt(system.unwrap_or(default_system));
+async fn run_agent(prompt: Option<String>, options: AgentOptions) -> Result<()> {
+    let AgentOptions {
+        verbose,
+        system,
+        stream,
+        resume,
+        overrides,
+        output_format,
+        allowed_tools,
+    } = options;
 
-    if let Some(task) = prompt {
-        // Single-shot mode
-        let response = agent.run(task).await?;
+    let config = Config::load(overrides)?;
+    let mut agent = agent::AgentLoop::new(&config).await?;
+    agent.restrict_tools(allowed_tools.as_deref())?;
+    let renderer = Arc::new(Mutex::new(agent::terminal::TerminalRenderer::new(verbose)));
+    if output_format == OutputFormat::Text {
+        let renderer = Arc::clone(&renderer);
+        agent.on_event(move |event| renderer.lock().unwrap().render(event));
+    }
+    agent.set_streaming(stream);
+    // Only the REPL can stop to ask the user for approval
+    agent.set_interactive(prompt.is_none());
+
+    if let Some(session) = resume {
+        // A resumed session keeps the system prompt it was started with
+        agent.resume_session(session)?;
+        eprintln!(
+            "{}",
+            format!(
+                "Resumed session {} ({} messages)",
+                agent.session_id().unwrap_or_default(),
+                agent.get_conversation_history().len()
+            )
+            .dimmed()
+        );
+    } else {
+        let system = system.unwrap_or_else(|| agent::DEFAULT_SYSTEM_PROMPT.to_string());
+        let cwd = std::env::current_dir().context("Failed to get current directory")?;
+        let instructions = agent::instructions::Instructions::load(&cwd)?;
+        if verbose {
+            for file in &instructions.files {
+                let mut line = format!("Instructions: {}", file.label);
+                for import in &file.imports {
+                    line.push_str(&format!(" (imports {})", import.display()));
+                }
+                eprintln!("{}", line.dimmed());
+            }
+        }
+        agent.set_system_prompt(instructions.apply(&system));
 
-        if !verbose {
-            println!("{}", response);
+        if let Err(e) = agent.start_session() {
+            eprintln!("{} {:#}", "Warning: session will not be saved:".yellow(), e);
         }
+    }
+
+    if let Some(task) = prompt {
+        // Single-shot mode
+        let code = run_headless(agent, task, output_format, !verbose && !stream).await?;
+        std::process::exit(code);
     } else {
         // Interactive REPL mode
-        run_interactive(agent).await?;
+        repl::Repl::new(agent, config, renderer, verbose, stream).run().await?;
     }
 
     Ok(())
 }
 
-async fn run_interactive(mut agent: agent::AgentLoop) -> Result<()> {
-    use rustyline::error::ReadlineError;
-    use rustyline::DefaultEditor;
+/// Run a single task and report how it went in `format`. Returns the
+/// process exit code for the outcome.
+async fn run_headless(
+    mut agent: agent::AgentLoop,
+    task: String,
+    format: OutputFormat,
+    print_answer: bool,
+) -> Result<i32> {
+    use std::io::Write;
 
-    println!("{}", "Code Agent - Interactive Mode".bright_cyan().bold());
-    println!("{}", "Type your requests or 'exit' to quit.".dimmed());
-    println!("{}", "Type 'clear' to clear conversation history.".dimmed());
-    println!();
+    let report = Arc::new(Mutex::new(output::RunReport::default()));
+    {
+        let report = Arc::clone(&report);
+        agent.on_event(move |event| report.lock().unwrap().record(event));
+    }
 
-    let mut rl = DefaultEditor::new()?;
+    let mut out = match format {
+        OutputFormat::Text => None,
+        _ => Some(output::take_stdout()?),
+    };
+    if let (OutputFormat::StreamJson, Some(out)) = (format, &out) {
+        let mut out = out.try_clone()?;
+        agent.on_event(move |event| {
+            if let Ok(line) = serde_json::to_string(event) {
+                let _ = writeln!(out, "{}", line);
+            }
+        });
+    }
 
-    loop {
-        match rl.readline(&format!("{} ", "You:".bright_cyan().bold())) {
-            Ok(line) => {
-                let line = line.trim();
+    let result = run_interruptible(&mut agent, task).await;
+    let mut report = std::mem::take(&mut *report.lock().unwrap());
+    report.finish(&result, agent.session_id());
 
-                if line.is_empty() {
-                    continue;
-                }
+    match (format, &mut out) {
+        (OutputFormat::Json, Some(out)) => {
+            writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        }
+        (OutputFormat::StreamJson, Some(out)) => {
+            let mut line = serde_json::to_value(&report)?;
+            line["type"] = json!("result");
+            writeln!(out, "{}", line)?;
+        }
+        _ => match &result {
+            Ok(answer) if print_answer => println!("{}", answer),
+            Ok(_) => {}
+            Err(e) if e.is::<agent::Interrupted>() => eprintln!("{}", "Interrupted".yellow()),
+            Err(e) => eprintln!("{} {:#}", "Error:".bright_red().bold(), e),
+        },
+    }
 
-                rl.add_history_entry(line)?;
-
-                match line {
-                    "exit" | "quit" => {
-                        println!("Goodbye!");
-                        break;
-                    }
-                    "clear" => {
-                        agent.clear_history();
-                        println!("{}", "Conversation history cleared.".dimmed());
-                        continue;
-                    }
-                    _ => {
-                        match agent.run(line.to_string()).await {
-                            Ok(_response) => {
-                                // Response already printed by agent loop if verbose
-                                // or printed as final message
-                                println!();
-                            }
-                            Err(e) => {
-                                eprintln!("{} {}", "Error:".bright_red().bold(), e);
-                                println!();
-                            }
-                        }
-                    }
-                }
-            }
-            Err(ReadlineError::Interrupted) => {
-                println!("Interrupted");
-                break;
-            }
-            Err(ReadlineError::Eof) => {
-                println!("EOF");
-                break;
+    // Keep stdout clean for piping the answer
+    eprintln!("{}", agent.usage().session_summary().dimmed());
+    Ok(report.exit_code)
+}
+
+/// Run one turn, cancelling it when Ctrl-C is pressed
+async fn run_interruptible(agent: &mut agent::AgentLoop, prompt: String) -> Result<String> {
+    let cancel = CancellationToken::new();
+    let on_ctrl_c = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
             }
-            Err(err) => {
-                eprintln!("Error: {:?}", err);
-                break;
+        })
+    };
+
+    let result = agent.run_cancellable(prompt, cancel).await;
+    on_ctrl_c.abort();
+    result
+}
+
+async fn run_sessions(action: SessionsAction, overrides: Vec<ConfigLayer>) -> Result<()> {
+    use agent::session;
+
+    match action {
+        SessionsAction::List => print_session_list()?,
+        SessionsAction::Show { id } => {
+            print_session(&session::load(&id)?);
+        }
+        SessionsAction::Resume {
+            id,
+            verbose,
+            stream,
+        } => {
+            let options = AgentOptions {
+                verbose,
+                stream,
+                resume: Some(session::load(&id)?),
+                overrides,
+                ..Default::default()
+            };
+            return run_agent(None, options).await;
+        }
+        SessionsAction::Delete { id } => {
+            let path = session::delete(&id)?;
+            println!("Deleted {}", path.display());
+        }
+        SessionsAction::Export { id, output } => {
+            let markdown = session::to_markdown(&session::load(&id)?);
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, markdown)
+                        .context(format!("Failed to write {}", path))?;
+                    println!("Exported to {}", path);
+                }
+                None => print!("{}", markdown),
             }
         }
     }
@@ -345,7 +706,115 @@ async fn run_interactive(mut agent: agent::AgentLoop) -> Result<()> {
     Ok(())
 }
 
-fn list_tool<T: Tool>(tool: &T) {
+fn print_custom_commands() -> Result<()> {
+    let commands = custom_commands::load();
+    if commands.is_empty() {
+        let dirs: Vec<String> = custom_commands::dirs()
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        println!("{}", format!("No custom commands in {}.", dirs.join(" or ")).dimmed());
+        return Ok(());
+    }
+    for command in commands {
+        let usage = match &command.argument_hint {
+            Some(hint) => format!("{} {}", command.name, hint),
+            None => command.name.clone(),
+        };
+        println!("  {:<24} {}", usage.bright_yellow(), command.description.dimmed());
+    }
+    Ok(())
+}
+
+fn print_session_list() -> Result<()> {
+    let sessions = agent::session::list()?;
+    if sessions.is_empty() {
+        println!("{}", "No saved sessions for this project.".dimmed());
+        return Ok(());
+    }
+    for info in sessions {
+        let preview: String = info
+            .first_prompt
+            .unwrap_or_default()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .take(60)
+            .collect();
+        println!(
+            "{}  {}  {:>4} msgs  {}",
+            info.id.bright_yellow(),
+            info.updated_at.format("%Y-%m-%d %H:%M").to_string().dimmed(),
+            info.message_count,
+            preview
+        );
+    }
+    Ok(())
+}
+
+fn print_session(session: &agent::session::Session) {
+    println!(
+        "{} {}",
+        "Session".bright_cyan().bold(),
+        session.header.id.bright_yellow()
+    );
+    println!(
+        "{}",
+        format!("{} · {}", session.header.cwd, session.header.model).dimmed()
+    );
+    println!();
+    print_messages(&session.messages);
+}
+
+fn print_messages(messages: &[agent::llm_client::Message]) {
+    for message in messages {
+        match message.role.as_str() {
+            "system" => println!("{} {}", "System:".dimmed(), message.content.dimmed()),
+            "user" => println!("{} {}", "You:".bright_cyan().bold(), message.content),
+            "assistant" => {
+                if !message.content.is_empty() {
+                    println!("{}", "Assistant:".bright_green().bold());
+                    println!("{}", message.content);
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    println!(
+                        "  {} {}",
+                        "→".bright_yellow(),
+                        format!("{}({})", call.function.name, call.function.arguments).dimmed()
+                    );
+                }
+            }
+            _ => println!(
+                "  {} {}",
+                "✓".bright_green(),
+                message.content.lines().next().unwrap_or_default().dimmed()
+            ),
+        }
+    }
+}
+
+fn show_config(config: &Config) {
+    println!("{}", "# Config files, lowest precedence first:".dimmed());
+    for path in config::files() {
+        let found = if path.exists() { "" } else { " (not found)" };
+        println!("{}", format!("#   {}{}", path.display(), found).dimmed());
+    }
+    println!();
+
+    let lines = config.describe();
+    let width = lines
+        .iter()
+        .map(|(key, value, _)| key.len() + value.len() + 3)
+        .max()
+        .unwrap_or(0);
+    for (key, value, source) in lines {
+        let setting = format!("{} = {}", key, value);
+        println!("{:<width$}  {}", setting, format!("# {}", source).dimmed(), width = width);
+    }
+}
+
+fn list_tool(tool: &dyn AsyncTool) {
     println!("  {} {}", "•".bright_green(), tool.name().bright_yellow().bold());
     println!("    {}", tool.description().dimmed());
     println!();
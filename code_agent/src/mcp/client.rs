@@ -0,0 +1,264 @@
+use super::protocol::{
+    CallToolResult, ListToolsResult, Message, RpcError, ToolInfo, METHOD_NOT_FOUND, PROTOCOL_VERSION,
+};
+use super::ServerConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Time a server gets to answer `initialize` and `tools/list`
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lines of the server's stderr kept for error messages
+const STDERR_LINES: usize = 20;
+
+/// How long to wait for the rest of a dead server's stderr
+const STDERR_DRAIN: Duration = Duration::from_millis(200);
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, RpcError>>>>>;
+
+/// Connection to an MCP server running as a child process and speaking
+/// JSON-RPC over its stdin and stdout
+pub struct McpClient {
+    name: String,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    /// Requests waiting for a response, by id
+    pending: Pending,
+    next_id: AtomicU64,
+    /// Last lines the server wrote to stderr
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    /// Cancelled once the server's stderr is closed
+    stderr_closed: CancellationToken,
+    reader: JoinHandle<()>,
+    _child: Child,
+}
+
+impl McpClient {
+    /// Start the server and complete the `initialize` handshake
+    pub async fn start(name: &str, config: &ServerConfig) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start MCP server '{}' ({})", name, config.command))?;
+
+        let stdin = child.stdin.take().context("MCP server has no stdin")?;
+        let stdin = Arc::new(tokio::sync::Mutex::new(stdin));
+        let stdout = child.stdout.take().context("MCP server has no stdout")?;
+        let stderr = Arc::new(Mutex::new(VecDeque::new()));
+        let stderr_closed = CancellationToken::new();
+        if let Some(pipe) = child.stderr.take() {
+            let stderr = Arc::clone(&stderr);
+            let closed = stderr_closed.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(pipe).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let mut stderr = stderr.lock().unwrap();
+                    if stderr.len() == STDERR_LINES {
+                        stderr.pop_front();
+                    }
+                    stderr.push_back(line);
+                }
+                closed.cancel();
+            });
+        }
+
+        let pending: Pending = Arc::default();
+        let reader = tokio::spawn(read_messages(
+            BufReader::new(stdout),
+            Arc::clone(&stdin),
+            Arc::clone(&pending),
+        ));
+
+        let client = Self {
+            name: name.to_string(),
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            stderr,
+            stderr_closed,
+            reader,
+            _child: child,
+        };
+        client.initialize().await?;
+        Ok(client)
+    }
+
+    async fn initialize(&self) -> Result<()> {
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": "code_agent", "version": env!("CARGO_PKG_VERSION")},
+        });
+        self.request("initialize", params, STARTUP_TIMEOUT, &CancellationToken::new())
+            .await
+            .with_context(|| format!("MCP server '{}' failed to initialize", self.name))?;
+        self.notify("notifications/initialized", json!({})).await
+    }
+
+    /// All tools the server offers, following pagination
+    pub async fn list_tools(&self) -> Result<Vec<ToolInfo>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self
+                .request("tools/list", params, STARTUP_TIMEOUT, &CancellationToken::new())
+                .await?;
+            let page: ListToolsResult = serde_json::from_value(result)
+                .with_context(|| format!("MCP server '{}' sent an invalid tools/list result", self.name))?;
+            tools.extend(page.tools);
+            match page.next_cursor {
+                Some(next) if Some(&next) != cursor.as_ref() => cursor = Some(next),
+                _ => return Ok(tools),
+            }
+        }
+    }
+
+    pub async fn call_tool(
+        &self,
+        tool: &str,
+        arguments: Value,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<CallToolResult> {
+        let params = json!({ "name": tool, "arguments": arguments });
+        let result = self.request("tools/call", params, timeout, cancel).await?;
+        serde_json::from_value(result)
+            .with_context(|| format!("MCP server '{}' sent an invalid tools/call result", self.name))
+    }
+
+    /// Send a request and wait for its response. On timeout or cancellation
+    /// the server is told to stop working on it. A JSON-RPC error response
+    /// is returned as an `RpcError`.
+    async fn request(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        if let Err(e) = self.send(&Message::request(id, method, params)).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+
+        let reason = tokio::select! {
+            response = rx => {
+                return match response {
+                    Ok(Ok(result)) => Ok(result),
+                    Ok(Err(error)) => Err(error.into()),
+                    Err(_) => Err(self.exited().await),
+                };
+            }
+            _ = tokio::time::sleep(timeout) => format!("timed out after {} ms", timeout.as_millis()),
+            _ = cancel.cancelled() => "cancelled".to_string(),
+        };
+
+        self.pending.lock().unwrap().remove(&id);
+        let _ = self
+            .notify("notifications/cancelled", json!({ "requestId": id, "reason": reason }))
+            .await;
+        bail!("MCP request {} to '{}' {}", method, self.name, reason)
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        self.send(&Message::notification(method, params)).await
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        match write_message(&self.stdin, message).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(self.exited().await),
+        }
+    }
+
+    /// Error for a server that went away, with what it last logged
+    async fn exited(&self) -> anyhow::Error {
+        let _ = tokio::time::timeout(STDERR_DRAIN, self.stderr_closed.cancelled()).await;
+        let stderr = self.stderr.lock().unwrap();
+        if stderr.is_empty() {
+            anyhow!("MCP server '{}' exited", self.name)
+        } else {
+            let log: Vec<&str> = stderr.iter().map(String::as_str).collect();
+            anyhow!("MCP server '{}' exited. Its last output:\n{}", self.name, log.join("\n"))
+        }
+    }
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn write_message(stdin: &tokio::sync::Mutex<ChildStdin>, message: &Message) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// Route responses to their waiting requests and answer requests from the
+/// server. Returns when the server closes its stdout; dropping the pending
+/// senders then fails every outstanding request.
+async fn read_messages<R: tokio::io::AsyncBufRead + Unpin>(
+    stdout: R,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+) {
+    let mut lines = stdout.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        // Servers may print non-protocol lines; skip them
+        let Ok(message) = serde_json::from_str::<Message>(&line) else {
+            continue;
+        };
+
+        match (message.id, message.method) {
+            // Requests from the server: only `ping` is supported
+            (Some(id), Some(method)) => {
+                let result = if method == "ping" {
+                    Ok(json!({}))
+                } else {
+                    Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method)))
+                };
+                let _ = write_message(&stdin, &Message::response(id, result)).await;
+            }
+            (Some(id), None) => {
+                let Some(tx) = id.as_u64().and_then(|id| pending.lock().unwrap().remove(&id)) else {
+                    continue;
+                };
+                let response = match message.error {
+                    Some(error) => Err(error),
+                    None => Ok(message.result.unwrap_or(Value::Null)),
+                };
+                let _ = tx.send(response);
+            }
+            // Notifications (logging, progress, list changes) are ignored
+            (None, _) => {}
+        }
+    }
+    pending.lock().unwrap().clear();
+}
@@ -0,0 +1,129 @@
+//! Model Context Protocol support: tools of external MCP servers are made
+//! available to the agent alongside the built-in ones, and the built-in
+//! tools can be served to other MCP clients
+
+pub mod client;
+pub mod protocol;
+pub mod server;
+pub mod tool;
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// An entry of the `[mcp_servers]` section of a `config.toml`, e.g.
+///
+/// ```toml
+/// [mcp_servers.tracker]
+/// command = "tracker-mcp"
+/// args = ["--stdio"]
+/// env = { TRACKER_TOKEN = "..." }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::tool_registry::ToolRegistry;
+    use crate::tools::{ToolContext, ToolParams, ToolResult};
+    use serde_json::{json, Value};
+    use std::time::{Duration, Instant};
+
+    fn fixture_servers() -> BTreeMap<String, ServerConfig> {
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mcp_server.py");
+        BTreeMap::from([(
+            "fixture".to_string(),
+            ServerConfig {
+                command: "python3".to_string(),
+                args: vec![script.to_string()],
+                env: BTreeMap::new(),
+            },
+        )])
+    }
+
+    async fn call(registry: &ToolRegistry, name: &str, args: Value, context: &ToolContext) -> ToolResult {
+        registry
+            .execute_tool(name, ToolParams { data: args }, context)
+            .await
+            .expect("MCP failures should be tool errors")
+    }
+
+    #[tokio::test]
+    async fn registers_and_calls_tools_of_a_stdio_server() {
+        let mut registry = ToolRegistry::new(true);
+        let errors = registry.register_mcp_servers(&fixture_servers()).await;
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let names: Vec<&str> = registry
+            .tools()
+            .map(|tool| tool.name())
+            .filter(|name| name.starts_with("mcp__"))
+            .collect();
+        assert_eq!(
+            names,
+            ["mcp__fixture__echo", "mcp__fixture__fail", "mcp__fixture__boom", "mcp__fixture__slow", "mcp__fixture__crash"]
+        );
+
+        let echo = registry.get("mcp__fixture__echo").unwrap();
+        assert_eq!(echo.description(), "Echo the given text");
+        assert_eq!(echo.parameters_schema()["required"], json!(["text"]));
+        assert!(echo.is_read_only());
+        assert_eq!(registry.get("mcp__fixture__crash").unwrap().parameters_schema()["type"], "object");
+
+        let context = ToolContext::default();
+        let result = call(&registry, "mcp__fixture__echo", json!({"text": "hello"}), &context).await;
+        assert!(result.success);
+        assert_eq!(result.output, "hello");
+
+        let result = call(&registry, "mcp__fixture__fail", json!({}), &context).await;
+        assert_eq!(result.error.as_deref(), Some("tracker is down"));
+
+        let result = call(&registry, "mcp__fixture__boom", json!({}), &context).await;
+        assert_eq!(result.error.as_deref(), Some("MCP error -32602: bad arguments"));
+
+        let cancelled = ToolContext::default();
+        let cancel = cancelled.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        });
+        let started = Instant::now();
+        let result = call(&registry, "mcp__fixture__slow", json!({"seconds": 30}), &cancelled).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(result.error.unwrap().contains("cancelled"));
+
+        // The server still answers after a cancelled request
+        let result = call(&registry, "mcp__fixture__echo", json!({"text": "still here"}), &context).await;
+        assert_eq!(result.output, "still here");
+
+        let result = call(&registry, "mcp__fixture__crash", json!({}), &context).await;
+        let error = result.error.unwrap();
+        assert!(error.contains("exited"), "{}", error);
+        assert!(error.contains("crashing on purpose"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn server_that_fails_to_start_is_reported() {
+        let servers = BTreeMap::from([(
+            "missing".to_string(),
+            ServerConfig {
+                command: "/nonexistent/mcp-server".to_string(),
+                args: Vec::new(),
+                env: BTreeMap::new(),
+            },
+        )]);
+
+        let mut registry = ToolRegistry::new(true);
+        let builtin = registry.tools().count();
+        let errors = registry.register_mcp_servers(&servers).await;
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("missing"));
+        assert_eq!(registry.tools().count(), builtin);
+    }
+}
@@ -0,0 +1,216 @@
+use super::protocol::{
+    CallToolResult, Message, RpcError, ToolAnnotations, ToolInfo, METHOD_NOT_FOUND, PROTOCOL_VERSION,
+};
+use crate::agent::permissions::{Decision, PermissionPolicy};
+use crate::agent::tool_registry::ToolRegistry;
+use crate::agent::validation;
+use crate::output::take_stdout;
+use crate::tools::{ToolContext, ToolParams, ToolResult};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Protocol revisions a client may ask for; others get `PROTOCOL_VERSION`
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", PROTOCOL_VERSION];
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Serves the registry's tools over MCP on stdin/stdout until stdin closes
+pub struct McpServer {
+    registry: Arc<ToolRegistry>,
+    permissions: PermissionPolicy,
+    /// Tokens of `tools/call` requests still running, by request id
+    running: Arc<Mutex<HashMap<String, CancellationToken>>>,
+}
+
+impl McpServer {
+    /// `permissions` decides which calls may run; there is nobody to ask,
+    /// so calls that would need approval are refused
+    pub fn new(registry: ToolRegistry, mut permissions: PermissionPolicy) -> Self {
+        permissions.set_interactive(false);
+        Self {
+            registry: Arc::new(registry),
+            permissions,
+            running: Arc::default(),
+        }
+    }
+
+    pub async fn serve(mut self) -> Result<()> {
+        let protocol_out = take_stdout()?;
+        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+        let writer = tokio::spawn(async move {
+            let mut out = tokio::fs::File::from_std(protocol_out);
+            while let Some(message) = rx.recv().await {
+                let mut line = serde_json::to_string(&message)?;
+                line.push('\n');
+                out.write_all(line.as_bytes()).await?;
+                out.flush().await?;
+            }
+            anyhow::Ok(())
+        });
+
+        let mut calls = JoinSet::new();
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await.context("Failed to read from stdin")? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: Message = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error = RpcError::new(PARSE_ERROR, format!("Parse error: {}", e));
+                    let _ = tx.send(Message::response(Value::Null, Err(error)));
+                    continue;
+                }
+            };
+            let Some(method) = message.method.as_deref() else {
+                // Responses to requests we never make
+                continue;
+            };
+            let params = message.params.clone().unwrap_or_else(|| json!({}));
+
+            let Some(id) = message.id else {
+                if method == "notifications/cancelled" {
+                    self.cancel(&params);
+                }
+                continue;
+            };
+
+            let result = match method {
+                "initialize" => Ok(initialize_result(&params)),
+                "ping" => Ok(json!({})),
+                "tools/list" => Ok(self.list_tools()),
+                "tools/call" => match self.start_call(&id, &params, &mut calls, tx.clone()).await {
+                    // The task responds when the tool finishes
+                    Ok(()) => continue,
+                    Err(error) => Err(error),
+                },
+                _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+            };
+            let _ = tx.send(Message::response(id, result));
+        }
+
+        // The client went away; stop whatever is still running
+        for token in self.running.lock().unwrap().values() {
+            token.cancel();
+        }
+        while calls.join_next().await.is_some() {}
+        drop(tx);
+        writer.await?
+    }
+
+    fn list_tools(&self) -> Value {
+        let tools: Vec<ToolInfo> = self
+            .registry
+            .tools()
+            .map(|tool| ToolInfo {
+                name: tool.name().to_string(),
+                description: Some(tool.description().to_string()),
+                input_schema: tool.parameters_schema(),
+                annotations: Some(ToolAnnotations {
+                    read_only_hint: Some(tool.is_read_only()),
+                }),
+            })
+            .collect();
+        json!({ "tools": tools })
+    }
+
+    /// Check a `tools/call` request and run it in the background. Problems
+    /// with the arguments or permissions are reported as a failed call so
+    /// the calling model can react; only unknown tools are protocol errors.
+    async fn start_call(
+        &mut self,
+        id: &Value,
+        params: &Value,
+        calls: &mut JoinSet<()>,
+        tx: mpsc::UnboundedSender<Message>,
+    ) -> Result<(), RpcError> {
+        let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+        let Some(tool) = self.registry.get(name) else {
+            return Err(RpcError::new(INVALID_PARAMS, format!("Unknown tool: {}", name)));
+        };
+        let args = match params.get("arguments") {
+            None | Some(Value::Null) => json!({}),
+            Some(args) => args.clone(),
+        };
+
+        let problems = validation::validate(&tool.parameters_schema(), &args);
+        let rejected = if !problems.is_empty() {
+            Some(ToolResult::error(format!(
+                "Invalid arguments for '{}':\n- {}\nExpected: {}",
+                name,
+                problems.join("\n- "),
+                validation::signature(name, &tool.parameters_schema())
+            )))
+        } else {
+            match self.permissions.check(name, &args, &CancellationToken::new()).await {
+                Decision::Allow => None,
+                Decision::Deny(reason) => Some(ToolResult::error(reason)),
+            }
+        };
+        if let Some(result) = rejected {
+            let _ = tx.send(Message::response(id.clone(), Ok(call_result(result))));
+            return Ok(());
+        }
+
+        let context = ToolContext::default();
+        let key = id.to_string();
+        self.running.lock().unwrap().insert(key.clone(), context.cancel.clone());
+
+        let registry = Arc::clone(&self.registry);
+        let running = Arc::clone(&self.running);
+        let (id, name) = (id.clone(), name.to_string());
+        calls.spawn(async move {
+            let result = registry
+                .execute_tool(&name, ToolParams { data: args }, &context)
+                .await
+                .unwrap_or_else(|e| ToolResult::error(format!("{:#}", e)));
+            running.lock().unwrap().remove(&key);
+            // The client does not expect a response to a cancelled request
+            if !context.cancel.is_cancelled() {
+                let _ = tx.send(Message::response(id, Ok(call_result(result))));
+            }
+        });
+        Ok(())
+    }
+
+    fn cancel(&self, params: &Value) {
+        if let Some(id) = params.get("requestId") {
+            if let Some(token) = self.running.lock().unwrap().get(&id.to_string()) {
+                token.cancel();
+            }
+        }
+    }
+}
+
+fn initialize_result(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = requested
+        .filter(|version| SUPPORTED_VERSIONS.contains(version))
+        .unwrap_or(PROTOCOL_VERSION);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "code_agent", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn call_result(result: ToolResult) -> Value {
+    let (text, is_error) = if result.success {
+        (result.output, false)
+    } else {
+        (result.error.unwrap_or_default(), true)
+    };
+    let result = CallToolResult {
+        content: vec![json!({ "type": "text", "text": text })],
+        is_error,
+    };
+    serde_json::to_value(result).unwrap_or_default()
+}
@@ -0,0 +1,98 @@
+use super::client::McpClient;
+use super::protocol::ToolInfo;
+use crate::tools::{AsyncTool, ToolContext, ToolParams, ToolResult};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Longest tool name model APIs accept
+const MAX_NAME_LEN: usize = 64;
+
+/// A tool offered by an MCP server, registered as `mcp__<server>__<tool>`
+pub struct McpTool {
+    client: Arc<McpClient>,
+    /// Namespaced name shown to the model
+    name: String,
+    /// Name the server knows the tool by
+    remote_name: String,
+    description: String,
+    input_schema: Value,
+    read_only: bool,
+}
+
+impl McpTool {
+    pub fn new(server: &str, client: Arc<McpClient>, info: ToolInfo) -> Self {
+        let mut input_schema = info.input_schema;
+        // Function definitions must describe an object, even without parameters
+        if input_schema.get("type").is_none() {
+            input_schema["type"] = "object".into();
+        }
+
+        Self {
+            client,
+            name: namespaced_name(server, &info.name),
+            description: info
+                .description
+                .unwrap_or_else(|| format!("{} (from MCP server {})", info.name, server)),
+            remote_name: info.name,
+            input_schema,
+            read_only: info
+                .annotations
+                .and_then(|annotations| annotations.read_only_hint)
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncTool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters_schema(&self) -> Value {
+        self.input_schema.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn execute(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
+        let called = self
+            .client
+            .call_tool(
+                &self.remote_name,
+                params.data,
+                context.limits.max_timeout,
+                &context.cancel,
+            )
+            .await;
+
+        // Transport and protocol errors are reported to the model like a
+        // failed call, not as a failure of the agent
+        Ok(match called {
+            Ok(result) if result.is_error => ToolResult::error(result.text()),
+            Ok(result) => ToolResult::success(result.text()),
+            Err(e) => ToolResult::error(format!("{:#}", e)),
+        })
+    }
+}
+
+/// `mcp__<server>__<tool>`, limited to the characters and length function
+/// names allow
+pub fn namespaced_name(server: &str, tool: &str) -> String {
+    let sanitize = |name: &str| -> String {
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect()
+    };
+    let mut name = format!("mcp__{}__{}", sanitize(server), sanitize(tool));
+    name.truncate(MAX_NAME_LEN);
+    name
+}
@@ -0,0 +1,272 @@
+//! Machine-readable output of headless agent runs (`--output-format`)
+
+use crate::agent::llm_client::Usage;
+use crate::agent::{AgentEvent, Interrupted, LlmRequestFailed, MaxIterationsExceeded};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::os::fd::FromRawFd;
+
+/// What `code_agent agent "<prompt>"` prints on stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The answer as plain text
+    #[default]
+    Text,
+    /// One JSON object describing the whole run, printed when it ends
+    Json,
+    /// Every agent event as a JSON object on its own line, then the result
+    StreamJson,
+}
+
+/// How a headless run ended. Each outcome has its own exit code; 2 is left
+/// to command line usage errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    #[default]
+    Success,
+    /// Any failure not covered below
+    Error,
+    MaxIterations,
+    /// The LLM could not be reached or kept failing
+    LlmError,
+    /// The agent answered, but at least one tool call was denied
+    PermissionDenied,
+    Interrupted,
+}
+
+impl RunStatus {
+    /// Classify the result of a turn
+    pub fn of(result: &Result<String>, denied: bool) -> Self {
+        match result {
+            Ok(_) if denied => RunStatus::PermissionDenied,
+            Ok(_) => RunStatus::Success,
+            Err(e) if e.is::<Interrupted>() => RunStatus::Interrupted,
+            Err(e) if e.is::<MaxIterationsExceeded>() => RunStatus::MaxIterations,
+            Err(e) if e.is::<LlmRequestFailed>() => RunStatus::LlmError,
+            Err(_) => RunStatus::Error,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunStatus::Success => 0,
+            RunStatus::Error => 1,
+            RunStatus::MaxIterations => 3,
+            RunStatus::LlmError => 4,
+            RunStatus::PermissionDenied => 5,
+            RunStatus::Interrupted => 130,
+        }
+    }
+}
+
+/// A tool call made during the run
+#[derive(Debug, Serialize)]
+pub struct ToolCallReport {
+    pub id: String,
+    pub name: String,
+    /// Parsed arguments, or the raw string if they were not valid JSON
+    pub arguments: Value,
+    pub success: bool,
+    /// Refused by the permission policy
+    pub denied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Token usage of the run
+#[derive(Debug, Default, Serialize)]
+pub struct UsageReport {
+    #[serde(flatten)]
+    pub tokens: Usage,
+    pub requests: u32,
+    pub cost_usd: Option<f64>,
+}
+
+/// Summary of a headless run, collected from its events. Printed as the
+/// single object of `--output-format json` and as the last line of
+/// `stream-json`.
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub status: RunStatus,
+    pub exit_code: i32,
+    pub answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub session_id: Option<String>,
+    pub tool_calls: Vec<ToolCallReport>,
+    /// Files written or edited by successful tool calls, in order of first change
+    pub files_changed: Vec<String>,
+    pub usage: UsageReport,
+}
+
+impl RunReport {
+    /// Take in an event of the run
+    pub fn record(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::ToolCall {
+                id,
+                name,
+                arguments,
+            } => self.tool_calls.push(ToolCallReport {
+                id: id.clone(),
+                name: name.clone(),
+                arguments: serde_json::from_str(arguments)
+                    .unwrap_or_else(|_| Value::String(arguments.clone())),
+                success: false,
+                denied: false,
+                error: None,
+            }),
+            AgentEvent::PermissionDenied { id, .. } => {
+                if let Some(call) = self.call_mut(id) {
+                    call.denied = true;
+                }
+            }
+            AgentEvent::ToolResult {
+                id,
+                success,
+                output,
+                ..
+            } => {
+                let Some(call) = self.call_mut(id) else {
+                    return;
+                };
+                call.success = *success;
+                if !success {
+                    call.error = Some(output.clone());
+                    return;
+                }
+                if !matches!(call.name.as_str(), "write" | "edit") {
+                    return;
+                }
+                let path = call.arguments["file_path"].as_str().map(str::to_string);
+                if let Some(path) = path {
+                    if !self.files_changed.contains(&path) {
+                        self.files_changed.push(path);
+                    }
+                }
+            }
+            AgentEvent::Usage {
+                tokens,
+                requests,
+                cost_usd,
+                ..
+            } => {
+                self.usage = UsageReport {
+                    tokens: *tokens,
+                    requests: *requests,
+                    cost_usd: *cost_usd,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether a tool call was refused by the permission policy
+    pub fn denied(&self) -> bool {
+        self.tool_calls.iter().any(|call| call.denied)
+    }
+
+    /// Fill in the outcome once the turn has ended
+    pub fn finish(&mut self, result: &Result<String>, session_id: Option<&str>) {
+        self.status = RunStatus::of(result, self.denied());
+        self.exit_code = self.status.exit_code();
+        self.session_id = session_id.map(str::to_string);
+        match result {
+            Ok(answer) => self.answer = Some(answer.clone()),
+            Err(e) => self.error = Some(format!("{:#}", e)),
+        }
+    }
+
+    fn call_mut(&mut self, id: &str) -> Option<&mut ToolCallReport> {
+        self.tool_calls.iter_mut().rev().find(|call| call.id == id)
+    }
+}
+
+/// Reserve the process's stdout for machine-readable output. File
+/// descriptor 1 is pointed at stderr, so anything else printed to stdout
+/// (e.g. by a tool) ends up there instead of corrupting the output.
+pub fn take_stdout() -> Result<File> {
+    // SAFETY: dup/dup2 only duplicate descriptors this process owns; the
+    // duplicate of stdout is owned by the returned File from here on
+    unsafe {
+        let reserved = libc::dup(libc::STDOUT_FILENO);
+        if reserved < 0 {
+            bail!("Failed to duplicate stdout: {}", std::io::Error::last_os_error());
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            bail!("Failed to redirect stdout: {}", std::io::Error::last_os_error());
+        }
+        Ok(File::from_raw_fd(reserved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str, name: &str, arguments: &str) -> AgentEvent {
+        AgentEvent::ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    fn result(id: &str, name: &str, success: bool) -> AgentEvent {
+        AgentEvent::ToolResult {
+            id: id.to_string(),
+            name: name.to_string(),
+            success,
+            output: if success { "ok" } else { "Permission denied" }.to_string(),
+        }
+    }
+
+    #[test]
+    fn report_collects_calls_changed_files_and_outcome() {
+        let mut report = RunReport::default();
+        let events = [
+            call("1", "write", r#"{"file_path": "a.txt", "content": "x"}"#),
+            result("1", "write", true),
+            call("2", "edit", r#"{"file_path": "a.txt"}"#),
+            result("2", "edit", true),
+            call("3", "write", r#"{"file_path": ".env"}"#),
+            AgentEvent::PermissionDenied {
+                id: "3".to_string(),
+                name: "write".to_string(),
+                reason: "Permission denied".to_string(),
+            },
+            result("3", "write", false),
+            call("4", "bash", "not json"),
+            result("4", "bash", true),
+        ];
+        for event in &events {
+            report.record(event);
+        }
+
+        assert_eq!(report.files_changed, ["a.txt"]);
+        assert_eq!(report.tool_calls.len(), 4);
+        assert!(report.tool_calls[2].denied);
+        assert_eq!(report.tool_calls[2].error.as_deref(), Some("Permission denied"));
+        assert_eq!(report.tool_calls[3].arguments, Value::String("not json".to_string()));
+
+        report.finish(&Ok("done".to_string()), None);
+        assert_eq!(report.status, RunStatus::PermissionDenied);
+        assert_eq!(report.exit_code, 5);
+    }
+
+    #[test]
+    fn errors_are_classified_by_type() {
+        let status = |error: anyhow::Error| RunStatus::of(&Err(error), false);
+        assert_eq!(status(MaxIterationsExceeded(25).into()), RunStatus::MaxIterations);
+        assert_eq!(status(Interrupted.into()), RunStatus::Interrupted);
+        assert_eq!(
+            status(anyhow::anyhow!("refused").context(LlmRequestFailed("Failed to get LLM response"))),
+            RunStatus::LlmError
+        );
+        assert_eq!(status(anyhow::anyhow!("disk full")), RunStatus::Error);
+    }
+}
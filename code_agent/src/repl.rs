@@ -0,0 +1,511 @@
+//! The interactive mode: prompts for the agent and `/` commands, built in
+//! or [custom](crate::custom_commands), with tab completion of their names
+
+use crate::agent::context::{self, estimate_tokens};
+use crate::agent::session;
+use crate::agent::terminal::TerminalRenderer;
+use crate::agent::{AgentLoop, Interrupted};
+use crate::config::{Config, Source};
+use crate::custom_commands::{self, CustomCommand};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, EventHandler, Helper, KeyEvent};
+use std::sync::{Arc, Mutex};
+
+/// A built-in command of the REPL
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        args: "",
+        description: "List the commands",
+    },
+    Command {
+        name: "model",
+        args: "[name]",
+        description: "Show the model, or switch to another one",
+    },
+    Command {
+        name: "tools",
+        args: "",
+        description: "List the tools the model can use",
+    },
+    Command {
+        name: "cost",
+        args: "",
+        description: "Show token usage and estimated cost",
+    },
+    Command {
+        name: "history",
+        args: "",
+        description: "Show the conversation so far",
+    },
+    Command {
+        name: "save",
+        args: "[file]",
+        description: "Export the session as Markdown",
+    },
+    Command {
+        name: "resume",
+        args: "[id]",
+        description: "List saved sessions, or continue one",
+    },
+    Command {
+        name: "compact",
+        args: "",
+        description: "Summarise older turns to free up context",
+    },
+    Command {
+        name: "system",
+        args: "[edit | prompt]",
+        description: "Show, edit or replace the system prompt",
+    },
+    Command {
+        name: "verbose",
+        args: "",
+        description: "Toggle showing iterations and tool calls",
+    },
+    Command {
+        name: "clear",
+        args: "",
+        description: "Start over, keeping the system prompt",
+    },
+    Command {
+        name: "exit",
+        args: "",
+        description: "Quit (also /quit)",
+    },
+];
+
+/// The command name and arguments of `line`, or None if it is a prompt.
+/// A first word with another `/` in it is a path, not a command.
+fn parse(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('/')?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+pub struct Repl {
+    agent: AgentLoop,
+    config: Config,
+    /// Shared with the agent's event observer, so `/verbose` reaches it
+    renderer: Arc<Mutex<TerminalRenderer>>,
+    verbose: bool,
+    stream: bool,
+    /// Custom commands, except those named like a built-in one
+    custom: Vec<CustomCommand>,
+}
+
+impl Repl {
+    pub fn new(
+        agent: AgentLoop,
+        config: Config,
+        renderer: Arc<Mutex<TerminalRenderer>>,
+        verbose: bool,
+        stream: bool,
+    ) -> Self {
+        Self {
+            agent,
+            config,
+            renderer,
+            verbose,
+            stream,
+            custom: Vec::new(),
+        }
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        println!("{}", "Code Agent - Interactive Mode".bright_cyan().bold());
+        println!("{}", "Type your requests, or /help for the commands.".dimmed());
+        println!("{}", "Press Ctrl-C to stop the agent, or twice at an empty prompt to quit.".dimmed());
+        println!();
+        self.load_custom_commands();
+
+        let mut rl: Editor<CommandHelper, DefaultHistory> = Editor::new()?;
+        let builtin = COMMANDS.iter().map(|command| command.name.to_string());
+        let custom = self.custom.iter().map(|command| command.name.clone());
+        rl.set_helper(Some(CommandHelper {
+            names: builtin.chain(custom).collect(),
+        }));
+        rl.bind_sequence(
+            KeyEvent::ctrl('C'),
+            EventHandler::Conditional(Box::new(ClearLineOnCtrlC)),
+        );
+        // Set by a Ctrl-C at an empty prompt; a second one in a row exits
+        let mut interrupted = false;
+
+        loop {
+            let input = rl.readline(&format!("{} ", "You:".bright_cyan().bold()));
+            if !matches!(input, Err(ReadlineError::Interrupted)) {
+                interrupted = false;
+            }
+            match input {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    rl.add_history_entry(line)?;
+
+                    // The commands of earlier versions still work without a slash
+                    let line = match line {
+                        "exit" | "quit" | "clear" | "cost" => format!("/{}", line),
+                        _ => line.to_string(),
+                    };
+                    match parse(&line) {
+                        Some(("exit" | "quit", _)) => {
+                            println!("Goodbye!");
+                            break;
+                        }
+                        Some((name, args)) => {
+                            let result = match self.custom.iter().find(|c| c.name == name).cloned() {
+                                Some(command) => self.custom_command(&command, args).await,
+                                None => self.command(name, args).await,
+                            };
+                            if let Err(e) = result {
+                                eprintln!("{} {:#}", "Error:".bright_red().bold(), e);
+                                println!();
+                            }
+                        }
+                        None => self.prompt(line).await,
+                    }
+                }
+                Err(ReadlineError::Interrupted) => {
+                    if interrupted {
+                        println!("Goodbye!");
+                        break;
+                    }
+                    interrupted = true;
+                    println!("{}", "(Press Ctrl-C again to exit)".dimmed());
+                }
+                Err(ReadlineError::Eof) => {
+                    println!("EOF");
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("Error: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_custom_commands(&mut self) {
+        for command in custom_commands::load() {
+            if COMMANDS.iter().any(|builtin| builtin.name == command.name) {
+                let message = format!(
+                    "{} is hidden by the built-in /{}",
+                    command.path.display(),
+                    command.name
+                );
+                eprintln!("{}", message.dimmed());
+            } else {
+                self.custom.push(command);
+            }
+        }
+    }
+
+    /// Run a custom command as a turn. Its model and tool restrictions only
+    /// apply to that turn.
+    async fn custom_command(&mut self, command: &CustomCommand, args: &str) -> Result<()> {
+        let cwd = std::env::current_dir().context("Failed to get current directory")?;
+        self.agent.restrict_tools(command.allowed_tools.as_deref())?;
+        let prompt = match command.prompt(args, &cwd, self.agent.permissions_mut()).await {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                self.agent.restrict_tools(None)?;
+                return Err(e);
+            }
+        };
+        let model = self.agent.model().to_string();
+        if let Some(command_model) = &command.model {
+            self.switch_model(command_model);
+        }
+
+        self.prompt(prompt).await;
+
+        if command.model.is_some() {
+            self.switch_model(&model);
+        }
+        self.agent.restrict_tools(None)
+    }
+
+    /// Switch to `model`; returns its context window
+    fn switch_model(&mut self, model: &str) -> usize {
+        // A configured window applies to every model
+        let context_window = match self.config.source("agent.context_window") {
+            Source::Default => context::context_window_for(model),
+            _ => self.config.agent.context_window,
+        };
+        self.agent.set_model(model.to_string(), context_window);
+        context_window
+    }
+
+    async fn prompt(&mut self, prompt: String) {
+        match crate::run_interruptible(&mut self.agent, prompt).await {
+            Ok(response) => {
+                // Verbose and streaming modes already printed the response
+                if !self.verbose && !self.stream {
+                    println!("{}", response);
+                }
+                println!();
+            }
+            Err(e) if e.is::<Interrupted>() => {
+                println!();
+                println!("{}", "Interrupted".yellow());
+                println!();
+            }
+            Err(e) => {
+                eprintln!("{} {}", "Error:".bright_red().bold(), e);
+                println!();
+            }
+        }
+    }
+
+    async fn command(&mut self, name: &str, args: &str) -> Result<()> {
+        match name {
+            "help" => {
+                for command in COMMANDS {
+                    let usage = format!("/{} {}", command.name, command.args);
+                    println!("  {:<24} {}", usage.bright_yellow(), command.description.dimmed());
+                }
+                if !self.custom.is_empty() {
+                    println!();
+                    println!("Custom commands:");
+                }
+                for command in &self.custom {
+                    let usage = format!(
+                        "/{} {}",
+                        command.name,
+                        command.argument_hint.as_deref().unwrap_or_default()
+                    );
+                    println!("  {:<24} {}", usage.bright_yellow(), command.description.dimmed());
+                }
+            }
+            "model" if args.is_empty() => println!("Model: {}", self.agent.model()),
+            "model" => {
+                let context_window = self.switch_model(args);
+                println!("Switched to {} ({} token context window)", args, context_window);
+            }
+            "tools" => {
+                let width = self.agent.tools().map(|tool| tool.name().len()).max().unwrap_or(0);
+                for tool in self.agent.tools() {
+                    let summary = tool.description().lines().next().unwrap_or_default();
+                    println!(
+                        "  {:<width$}  {}",
+                        tool.name().bright_yellow(),
+                        summary.dimmed(),
+                        width = width
+                    );
+                }
+            }
+            "cost" => {
+                println!("{}", self.agent.usage().turn_summary());
+                println!("{}", self.agent.usage().session_summary());
+            }
+            "history" => {
+                let history = self.agent.get_conversation_history();
+                // `/system` shows the prompt
+                let start = usize::from(self.agent.system_prompt().is_some());
+                if history.len() == start {
+                    println!("{}", "No messages yet.".dimmed());
+                }
+                crate::print_messages(&history[start..]);
+            }
+            "save" => {
+                let id = self
+                    .agent
+                    .session_id()
+                    .context("This conversation is not being saved")?
+                    .to_string();
+                let path = match args {
+                    "" => format!("session-{}.md", id),
+                    path => path.to_string(),
+                };
+                let markdown = session::to_markdown(&session::load(&id)?);
+                std::fs::write(&path, markdown).context(format!("Failed to write {}", path))?;
+                println!("Saved to {}", path);
+            }
+            "resume" if args.is_empty() => crate::print_session_list()?,
+            "resume" => {
+                self.agent.resume_session(session::load(args)?)?;
+                println!(
+                    "Resumed session {} ({} messages)",
+                    self.agent.session_id().unwrap_or_default(),
+                    self.agent.get_conversation_history().len()
+                );
+            }
+            "compact" => {
+                let before = estimate_tokens(self.agent.get_conversation_history());
+                if !self.agent.compact().await? {
+                    println!("{}", "Nothing to compact yet.".dimmed());
+                } else if !self.verbose {
+                    // Verbose mode already reported the compaction
+                    let after = estimate_tokens(self.agent.get_conversation_history());
+                    println!("Compacted history (~{} → ~{} tokens)", before, after);
+                }
+            }
+            "system" => match args {
+                "" => match self.agent.system_prompt() {
+                    Some(prompt) => println!("{}", prompt),
+                    None => println!("{}", "No system prompt.".dimmed()),
+                },
+                "edit" => {
+                    let current = self.agent.system_prompt().unwrap_or_default().to_string();
+                    let edited = edit_text(&current)?;
+                    if edited.trim() == current.trim() {
+                        println!("{}", "System prompt unchanged.".dimmed());
+                    } else {
+                        self.agent.replace_system_prompt(edited.trim().to_string());
+                        println!("System prompt updated.");
+                    }
+                }
+                prompt => {
+                    self.agent.replace_system_prompt(prompt.to_string());
+                    println!("System prompt replaced.");
+                }
+            },
+            "verbose" => {
+                self.verbose = !self.verbose;
+                self.renderer.lock().unwrap().set_verbose(self.verbose);
+                println!("Verbose output {}", if self.verbose { "on" } else { "off" });
+            }
+            "clear" => {
+                self.agent.clear_history();
+                println!("{}", "Conversation history cleared.".dimmed());
+            }
+            _ => bail!("Unknown command /{}; /help lists the commands", name),
+        }
+        println!();
+        Ok(())
+    }
+}
+
+/// Let the user edit `text` in `$VISUAL` or `$EDITOR` (vi if neither is set)
+fn edit_text(text: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // The variable may hold arguments, e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("$EDITOR is empty")?;
+
+    let path = std::env::temp_dir().join(format!("code_agent_system_{}.md", std::process::id()));
+    std::fs::write(&path, text).context(format!("Failed to write {}", path.display()))?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .context(format!("Failed to run {}", program));
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    if !status?.success() {
+        bail!("{} exited with an error; the system prompt was not changed", program);
+    }
+    edited.context("Failed to read the edited prompt")
+}
+
+/// Completes command names after a `/` at the start of the line
+struct CommandHelper {
+    names: Vec<String>,
+}
+
+impl CommandHelper {
+    fn candidates(&self, line: &str) -> Vec<Pair> {
+        let Some(prefix) = line.strip_prefix('/') else {
+            return Vec::new();
+        };
+        if prefix.contains(char::is_whitespace) {
+            return Vec::new();
+        }
+        self.names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Pair {
+                display: format!("/{}", name),
+                replacement: format!("/{} ", name),
+            })
+            .collect()
+    }
+}
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok((0, self.candidates(&line[..pos])))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
+
+/// Ctrl-C clears a partly typed line; only at an empty prompt does it
+/// interrupt `readline`
+struct ClearLineOnCtrlC;
+
+impl rustyline::ConditionalEventHandler for ClearLineOnCtrlC {
+    fn handle(
+        &self,
+        _evt: &rustyline::Event,
+        _n: rustyline::RepeatCount,
+        _positive: bool,
+        ctx: &rustyline::EventContext,
+    ) -> Option<rustyline::Cmd> {
+        if ctx.line().is_empty() {
+            None
+        } else {
+            Some(rustyline::Cmd::Kill(rustyline::Movement::WholeLine))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_and_completed() {
+        assert_eq!(parse("/model gpt-4o"), Some(("model", "gpt-4o")));
+        assert_eq!(parse("/system  Be brief. "), Some(("system", "Be brief.")));
+        assert_eq!(parse("/etc/hosts looks wrong"), None);
+        assert_eq!(parse("fix the tests"), None);
+
+        let helper = CommandHelper {
+            names: COMMANDS.iter().map(|command| command.name.to_string()).collect(),
+        };
+        let names: Vec<_> = helper.candidates("/co").into_iter().map(|c| c.replacement).collect();
+        assert_eq!(names, ["/cost ", "/compact "]);
+        assert!(helper.candidates("/model gp").is_empty());
+        assert!(helper.candidates("co").is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+//! `code_agent serve`: an HTTP API for running agent sessions remotely
+//!
+//! ```text
+//! POST   /sessions               create a session  {"system_prompt"?, "cwd"?}
+//! GET    /sessions               list sessions
+//! GET    /sessions/{id}          session state
+//! DELETE /sessions/{id}          cancel its turn and forget it
+//! GET    /sessions/{id}/messages conversation history
+//! POST   /sessions/{id}/messages start a turn      {"content"}
+//! GET    /sessions/{id}/events   agent events as server-sent events
+//! POST   /sessions/{id}/cancel   cancel the running turn
+//! ```
+
+pub mod session;
+
+use crate::agent::DEFAULT_SYSTEM_PROMPT;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use colored::*;
+use serde::Deserialize;
+use serde_json::json;
+use session::RemoteSession;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+struct AppState {
+    sessions: RwLock<BTreeMap<String, Arc<RemoteSession>>>,
+    /// Session working directories must be inside this directory
+    root: PathBuf,
+    /// Settings every session starts with
+    config: Config,
+}
+
+type SharedState = Arc<AppState>;
+
+/// Serve the API on `addr` until Ctrl-C
+pub async fn serve(addr: &str, config: Config) -> Result<()> {
+    let root = std::env::current_dir()
+        .and_then(|cwd| cwd.canonicalize())
+        .context("Failed to get current directory")?;
+    let state = Arc::new(AppState {
+        sessions: RwLock::default(),
+        root,
+        config,
+    });
+
+    let app = router(Arc::clone(&state));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .context(format!("Failed to listen on {}", addr))?;
+    eprintln!(
+        "{} http://{}",
+        "Serving the agent API on".bright_cyan().bold(),
+        listener.local_addr()?
+    );
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            // Stop running turns so their tools are cleaned up. Dropping the
+            // sessions also ends their event streams, which would otherwise
+            // keep the server from shutting down.
+            let sessions = std::mem::take(&mut *state.sessions.write().unwrap());
+            for session in sessions.values() {
+                session.cancel();
+            }
+        })
+        .await
+        .context("Server failed")
+}
+
+fn router(state: SharedState) -> Router {
+    Router::new()
+        .route("/sessions", post(create_session).get(list_sessions))
+        .route("/sessions/{id}", get(get_session).delete(delete_session))
+        .route("/sessions/{id}/messages", get(get_messages).post(post_message))
+        .route("/sessions/{id}/events", get(stream_events))
+        .route("/sessions/{id}/cancel", post(cancel_turn))
+        .with_state(state)
+}
+
+/// An error response with a JSON body `{"error": "..."}`
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+fn find(state: &AppState, id: &str) -> ApiResult<Arc<RemoteSession>> {
+    state
+        .sessions
+        .read()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No session with id '{}'", id)))
+}
+
+#[derive(Deserialize)]
+struct CreateSession {
+    system_prompt: Option<String>,
+    /// Working directory, relative to the server's; defaults to the server's
+    cwd: Option<String>,
+}
+
+async fn create_session(
+    State(state): State<SharedState>,
+    Json(request): Json<CreateSession>,
+) -> ApiResult<impl IntoResponse> {
+    let cwd = match &request.cwd {
+        Some(cwd) => state.root.join(cwd),
+        None => state.root.clone(),
+    };
+    let cwd = cwd
+        .canonicalize()
+        .ok()
+        .filter(|cwd| cwd.is_dir())
+        .ok_or_else(|| {
+            ApiError(StatusCode::BAD_REQUEST, format!("Not a directory: {}", cwd.display()))
+        })?;
+    if !cwd.starts_with(&state.root) {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("{} is outside {}", cwd.display(), state.root.display()),
+        ));
+    }
+
+    let system_prompt = request
+        .system_prompt
+        .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string());
+    let session = RemoteSession::start(cwd, system_prompt, &state.config)
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+
+    let info = session.info();
+    state
+        .sessions
+        .write()
+        .unwrap()
+        .insert(session.id.clone(), Arc::new(session));
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn list_sessions(State(state): State<SharedState>) -> impl IntoResponse {
+    let sessions: Vec<_> = state
+        .sessions
+        .read()
+        .unwrap()
+        .values()
+        .map(|session| session.info())
+        .collect();
+    Json(json!({ "sessions": sessions }))
+}
+
+async fn get_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(find(&state, &id)?.info()))
+}
+
+async fn delete_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    let session = state.sessions.write().unwrap().remove(&id);
+    let session =
+        session.ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No session with id '{}'", id)))?;
+    session.cancel();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_messages(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    let session = find(&state, &id)?;
+    Ok(Json(json!({
+        "running": session.is_running(),
+        "messages": session.history(),
+    })))
+}
+
+#[derive(Deserialize)]
+struct PostMessage {
+    content: String,
+}
+
+async fn post_message(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(request): Json<PostMessage>,
+) -> ApiResult<impl IntoResponse> {
+    let session = find(&state, &id)?;
+    session.send(request.content).map_err(|_| {
+        ApiError(
+            StatusCode::CONFLICT,
+            "A turn is already running; wait for it to finish or cancel it".to_string(),
+        )
+    })?;
+    Ok((StatusCode::ACCEPTED, Json(session.info())))
+}
+
+async fn stream_events(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let session = find(&state, &id)?;
+    let events = BroadcastStream::new(session.subscribe()).map(|event| {
+        Ok(match event {
+            Ok(event) => Event::default().json_data(event).unwrap_or_default(),
+            // The client was too slow; tell it how much it missed
+            Err(lagged) => Event::default()
+                .event("lagged")
+                .data(lagged.to_string()),
+        })
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn cancel_turn(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    if find(&state, &id)?.cancel() {
+        Ok(StatusCode::ACCEPTED)
+    } else {
+        Err(ApiError(StatusCode::CONFLICT, "No turn is running".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigLayer, Source};
+    use std::time::Duration;
+
+    /// Serve `app` on a free local port
+    async fn spawn(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    /// Read the event stream until it contains `needle`
+    async fn read_until(events: &mut reqwest::Response, needle: &str) -> String {
+        let mut received = String::new();
+        while !received.contains(needle) {
+            let chunk = tokio::time::timeout(Duration::from_secs(10), events.chunk())
+                .await
+                .expect("timed out waiting for events")
+                .unwrap()
+                .expect("event stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn turns_stream_events_and_run_one_at_a_time() {
+        // An LLM that answers after a moment, so the turn is still running
+        // when the second message arrives
+        let llm = spawn(Router::new().route(
+            "/chat/completions",
+            post(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Json(json!({
+                    "choices": [{ "message": { "role": "assistant", "content": "Hello there" } }],
+                    "usage": { "prompt_tokens": 10, "completion_tokens": 2 }
+                }))
+            }),
+        ))
+        .await;
+
+        let root = std::env::temp_dir().join(format!("code_agent_server_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let layer: ConfigLayer = toml::from_str(&format!(
+            "[llm]\nbase_url = \"{}\"\napi_key = \"test\"\nmodel = \"gpt-4o\"",
+            llm
+        ))
+        .unwrap();
+        let state = Arc::new(AppState {
+            sessions: RwLock::default(),
+            root: root.clone(),
+            config: Config::from_layers(vec![(Source::CommandLine, layer)]).unwrap(),
+        });
+        let api = spawn(router(state)).await;
+        let client = reqwest::Client::new();
+
+        let created = client.post(format!("{}/sessions", api)).json(&json!({})).send().await.unwrap();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let id = created.json::<serde_json::Value>().await.unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let mut events = client
+            .get(format!("{}/sessions/{}/events", api, id))
+            .send()
+            .await
+            .unwrap();
+
+        let send = |content: &str| {
+            client
+                .post(format!("{}/sessions/{}/messages", api, id))
+                .json(&json!({ "content": content }))
+                .send()
+        };
+        assert_eq!(send("Hi").await.unwrap().status(), StatusCode::ACCEPTED);
+        assert_eq!(send("Hi again").await.unwrap().status(), StatusCode::CONFLICT);
+
+        let received = read_until(&mut events, "\"type\":\"finished\"").await;
+        assert!(received.contains("\"type\":\"turn_started\""));
+        assert!(received.contains("Hello there"));
+
+        // The next message is taken as soon as the turn is reported finished
+        assert_eq!(send("Hi again").await.unwrap().status(), StatusCode::ACCEPTED);
+        read_until(&mut events, "\"type\":\"finished\"").await;
+        let messages: serde_json::Value = client
+            .get(format!("{}/sessions/{}/messages", api, id))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(messages["running"], false);
+        assert_eq!(messages["messages"].as_array().unwrap().len(), 5);
+
+        let _ = std::fs::remove_file(
+            crate::agent::session::project_dir(&root).unwrap().join(format!("{}.jsonl", id)),
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::agent::instructions::Instructions;
+use crate::agent::llm_client::Message;
+use crate::agent::{AgentEvent, AgentLoop};
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// Events kept for subscribers that fall behind
+const EVENT_BUFFER: usize = 1024;
+
+/// An agent conversation driven over HTTP. Each session has its own
+/// `AgentLoop`, tools and working directory; one turn runs at a time.
+pub struct RemoteSession {
+    pub id: String,
+    pub cwd: PathBuf,
+    agent: tokio::sync::Mutex<AgentLoop>,
+    events: broadcast::Sender<AgentEvent>,
+    /// Cancels the running turn, if there is one
+    turn: Mutex<Option<CancellationToken>>,
+    /// The event that ended the running turn, held back until the session
+    /// takes the next message
+    ending: Arc<Mutex<Option<AgentEvent>>>,
+    /// History as of the end of the last turn, readable while a turn runs
+    history: Mutex<Vec<Message>>,
+}
+
+/// What `GET /sessions/{id}` returns
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub cwd: String,
+    pub running: bool,
+    pub message_count: usize,
+}
+
+/// Returned when a message is posted while a turn is still running
+#[derive(Debug)]
+pub struct Busy;
+
+impl RemoteSession {
+    /// Set up an agent working in `cwd` and start recording its session
+    pub async fn start(
+        cwd: PathBuf,
+        system_prompt: String,
+        config: &Config,
+    ) -> Result<Self> {
+        let mut agent = AgentLoop::new(config).await?;
+        // Nobody can answer a prompt; calls needing approval are denied
+        agent.set_interactive(false);
+        agent.set_working_dir(cwd.clone());
+        let instructions = Instructions::load(&cwd)?;
+        agent.set_system_prompt(instructions.apply(&system_prompt));
+        agent.start_session().context("Failed to create the session file")?;
+
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let ending = Arc::new(Mutex::new(None));
+        let sender = events.clone();
+        let held = Arc::clone(&ending);
+        agent.on_event(move |event| {
+            if matches!(
+                event,
+                AgentEvent::Finished { .. } | AgentEvent::Interrupted | AgentEvent::Error { .. }
+            ) {
+                *held.lock().unwrap() = Some(event.clone());
+                return;
+            }
+            // No subscribers is fine; events are only streamed live
+            let _ = sender.send(event.clone());
+        });
+
+        Ok(Self {
+            id: agent.session_id().unwrap_or_default().to_string(),
+            cwd,
+            history: Mutex::new(agent.get_conversation_history().to_vec()),
+            agent: tokio::sync::Mutex::new(agent),
+            events,
+            turn: Mutex::new(None),
+            ending,
+        })
+    }
+
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            cwd: self.cwd.display().to_string(),
+            running: self.is_running(),
+            message_count: self.history.lock().unwrap().len(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.turn.lock().unwrap().is_some()
+    }
+
+    pub fn history(&self) -> Vec<Message> {
+        self.history.lock().unwrap().clone()
+    }
+
+    /// Events from now on, until the session is dropped
+    pub fn subscribe(&self) -> broadcast::Receiver<AgentEvent> {
+        self.events.subscribe()
+    }
+
+    /// Run a turn with `prompt` in the background. Progress is reported as
+    /// events; fails with `Busy` if the previous turn has not finished.
+    pub fn send(self: &Arc<Self>, prompt: String) -> Result<(), Busy> {
+        let cancel = {
+            let mut turn = self.turn.lock().unwrap();
+            if turn.is_some() {
+                return Err(Busy);
+            }
+            turn.insert(CancellationToken::new()).clone()
+        };
+
+        let session = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut agent = session.agent.lock().await;
+            let _ = agent.run_cancellable(prompt, cancel).await;
+            *session.history.lock().unwrap() = agent.get_conversation_history().to_vec();
+            drop(agent);
+
+            // The outcome reaches subscribers as a finished, interrupted or
+            // error event once the next message would be accepted
+            let mut turn = session.turn.lock().unwrap();
+            *turn = None;
+            if let Some(event) = session.ending.lock().unwrap().take() {
+                let _ = session.events.send(event);
+            }
+        });
+        Ok(())
+    }
+
+    /// Cancel the running turn. Returns false if there is none.
+    pub fn cancel(&self) -> bool {
+        match self.turn.lock().unwrap().as_ref() {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
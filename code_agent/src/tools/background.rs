@@ -0,0 +1,287 @@
+use super::bash::{bash_command, capture_into, kill_group, Capture};
+use super::{parameters_schema, Tool, ToolContext, ToolParams, ToolResult};
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most output returned by a single `bash_output` call
+const MAX_READ_BYTES: usize = 30_000;
+
+/// A command started with `run_in_background`
+struct Job {
+    command: String,
+    child: Child,
+    /// Interleaved stdout and stderr
+    output: Arc<Mutex<Vec<u8>>>,
+    /// How much of `output` has been returned already
+    read: usize,
+    started: Instant,
+    status: Option<ExitStatus>,
+    _pipes: [Capture; 2],
+}
+
+impl Job {
+    fn poll(&mut self) -> Option<ExitStatus> {
+        if self.status.is_none() {
+            self.status = self.child.try_wait().ok().flatten();
+        }
+        self.status
+    }
+
+    fn describe(&mut self) -> String {
+        let state = match self.poll() {
+            None => "running".to_string(),
+            Some(status) => match status.code() {
+                Some(code) => format!("exited with code {}", code),
+                None => "killed".to_string(),
+            },
+        };
+        format!(
+            "{}, started {} ago: {}",
+            state,
+            format_elapsed(self.started.elapsed()),
+            self.command
+        )
+    }
+}
+
+/// Commands the agent left running in the background, keyed by handle
+/// (`bg-1`, `bg-2`, ...). Dropping the table kills every job that is still
+/// running, so jobs end together with the `AgentLoop` owning the registry.
+#[derive(Default)]
+pub struct BackgroundJobs {
+    jobs: Mutex<BTreeMap<u32, Job>>,
+}
+
+impl BackgroundJobs {
+    /// Start a command in the context's working directory and return its
+    /// handle
+    pub fn start(&self, command: &str, context: &ToolContext) -> Result<String> {
+        let mut child = bash_command(context)
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .spawn()
+            .context("Failed to start background command")?;
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let pipes = [
+            capture_into(child.stdout.take(), Arc::clone(&output)),
+            capture_into(child.stderr.take(), Arc::clone(&output)),
+        ];
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(1, |last| last + 1);
+        jobs.insert(
+            id,
+            Job {
+                command: command.to_string(),
+                child,
+                output,
+                read: 0,
+                started: Instant::now(),
+                status: None,
+                _pipes: pipes,
+            },
+        );
+        Ok(format!("bg-{}", id))
+    }
+
+    /// Output produced since the previous call, plus the job's state
+    pub fn read_output(&self, handle: &str) -> Result<String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&parse_handle(handle)?).context(unknown(handle))?;
+
+        // Poll before reading so output written right before exit is included
+        let state = job.describe();
+        let output = job.output.lock().unwrap();
+        let end = (job.read + MAX_READ_BYTES).min(output.len());
+        let new = String::from_utf8_lossy(&output[job.read..end]).to_string();
+        let remaining = output.len() - end;
+        drop(output);
+        job.read = end;
+
+        let mut result = format!("[{} {}]\n", handle, state);
+        if new.is_empty() {
+            result.push_str("(no new output)");
+        } else {
+            result.push_str(&new);
+        }
+        if remaining > 0 {
+            result.push_str(&format!(
+                "\n[{} more bytes; call bash_output again to continue]",
+                remaining
+            ));
+        }
+        Ok(result)
+    }
+
+    /// State of one job, or of all jobs when `handle` is `None`
+    pub fn status(&self, handle: Option<&str>) -> Result<String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(handle) = handle {
+            let job = jobs.get_mut(&parse_handle(handle)?).context(unknown(handle))?;
+            return Ok(format!("{}: {}", handle, job.describe()));
+        }
+
+        if jobs.is_empty() {
+            return Ok("No background jobs".to_string());
+        }
+        Ok(jobs
+            .iter_mut()
+            .map(|(id, job)| format!("bg-{}: {}", id, job.describe()))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Stop a job and everything it started (SIGTERM, then SIGKILL)
+    pub fn kill(&self, handle: &str) -> Result<String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&parse_handle(handle)?).context(unknown(handle))?;
+
+        let exited = job.poll().is_some();
+        kill_group(&mut job.child, exited);
+        job.status = job.child.wait().ok();
+        Ok(format!("{}: {}", handle, job.describe()))
+    }
+}
+
+impl Drop for BackgroundJobs {
+    fn drop(&mut self) {
+        for job in self.jobs.get_mut().unwrap().values_mut() {
+            let exited = job.poll().is_some();
+            kill_group(&mut job.child, exited);
+            let _ = job.child.wait();
+        }
+    }
+}
+
+fn parse_handle(handle: &str) -> Result<u32> {
+    handle
+        .trim()
+        .strip_prefix("bg-")
+        .and_then(|id| id.parse().ok())
+        .context(unknown(handle))
+}
+
+fn unknown(handle: &str) -> String {
+    format!("Unknown background job '{}'", handle)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct JobParams {
+    /// Job handle returned by bash with run_in_background, e.g. bg-1
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StatusParams {
+    /// Job handle; omit to list all background jobs
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// Tool for reading new output of a background job
+pub struct BashOutputTool {
+    pub jobs: Arc<BackgroundJobs>,
+}
+
+impl Tool for BashOutputTool {
+    fn name(&self) -> &str {
+        "bash_output"
+    }
+
+    fn description(&self) -> &str {
+        "Reads the output a background bash job produced since the last call, along with whether it is still running"
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<JobParams>()
+    }
+
+    fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        let params: JobParams = serde_json::from_value(params.data)
+            .context("Failed to parse bash_output parameters")?;
+
+        match self.jobs.read_output(&params.id) {
+            Ok(output) => Ok(ToolResult::success(output)),
+            Err(e) => Ok(ToolResult::error(e.to_string())),
+        }
+    }
+}
+
+/// Tool for checking whether background jobs are still running
+pub struct BashStatusTool {
+    pub jobs: Arc<BackgroundJobs>,
+}
+
+impl Tool for BashStatusTool {
+    fn name(&self) -> &str {
+        "bash_status"
+    }
+
+    fn description(&self) -> &str {
+        "Shows whether background bash jobs are running or how they exited"
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<StatusParams>()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        let params: StatusParams = serde_json::from_value(params.data)
+            .context("Failed to parse bash_status parameters")?;
+
+        match self.jobs.status(params.id.as_deref()) {
+            Ok(status) => Ok(ToolResult::success(status)),
+            Err(e) => Ok(ToolResult::error(e.to_string())),
+        }
+    }
+}
+
+/// Tool for stopping a background job
+pub struct BashKillTool {
+    pub jobs: Arc<BackgroundJobs>,
+}
+
+impl Tool for BashKillTool {
+    fn name(&self) -> &str {
+        "bash_kill"
+    }
+
+    fn description(&self) -> &str {
+        "Stops a background bash job and every process it started"
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<JobParams>()
+    }
+
+    fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        let params: JobParams = serde_json::from_value(params.data)
+            .context("Failed to parse bash_kill parameters")?;
+
+        match self.jobs.kill(&params.id) {
+            Ok(status) => Ok(ToolResult::success(status)),
+            Err(e) => Ok(ToolResult::error(e.to_string())),
+        }
+    }
+}
@@ -1,50 +1,184 @@
-use super::{Tool, ToolParams, ToolResult};
+use super::background::BackgroundJobs;
+use super::shell::ShellSession;
+use super::{parameters_schema, Tool, ToolContext, ToolParams, ToolResult};
 use anyhow::{Context, Result};
-use serde::Deserialize;
-use std::process::Command;
-use std::time::Duration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Default upper bound for the `timeout` parameter (10 minutes)
+pub const MAX_TIMEOUT_MS: u64 = 600_000;
+
+/// Time between SIGTERM and SIGKILL when a command times out
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// How long to keep reading output after a command exits while processes
+/// it started in the background still hold its pipes
+const EXIT_DRAIN: Duration = Duration::from_millis(200);
+
+pub(super) const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 /// Tool for executing bash commands
-pub struct BashTool;
+///
+/// With a persistent shell, commands share one `bash` process so `cd`,
+/// exported variables and shell functions carry over between calls;
+/// otherwise every command runs in a fresh `bash -c`.
+#[derive(Default)]
+pub struct BashTool {
+    shell: Option<Mutex<Option<ShellSession>>>,
+    /// Where `run_in_background` commands go; not available outside the agent
+    jobs: Option<Arc<BackgroundJobs>>,
+}
 
-#[derive(Debug, Deserialize)]
-struct BashParams {
-    command: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BashParams {
+    /// The bash command to execute
+    pub command: String,
+    /// Optional human-readable description of what the command does
     #[serde(default)]
-    description: Option<String>,
+    pub description: Option<String>,
+    /// Optional timeout in milliseconds. Default is 120000 (2 minutes),
+    /// maximum 600000 (10 minutes). On timeout the command is killed and
+    /// its partial output returned.
     #[serde(default = "default_timeout")]
-    timeout: u64,
+    pub timeout: u64,
+    /// Run in a new, isolated shell instead of the persistent one.
+    /// Defaults to false.
+    #[serde(default)]
+    pub fresh: bool,
+    /// Start the command in the background (e.g. a dev server or long test
+    /// run) and return a job handle immediately. The timeout does not
+    /// apply. Defaults to false.
+    #[serde(default)]
+    pub run_in_background: bool,
 }
 
 fn default_timeout() -> u64 {
     120000 // 2 minutes in milliseconds
 }
 
+/// How a command ended
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// The command finished with this exit code (`None` if killed by a signal)
+    Exited(Option<i32>),
+    /// The deadline passed and the command was killed
+    TimedOut,
+    /// The tool call was cancelled and the command was killed
+    Cancelled,
+    /// The persistent shell itself exited, e.g. because of `exit`
+    ShellExited(Option<i32>),
+}
+
+impl BashTool {
+    /// Tool backed by a persistent shell, started on first use
+    pub fn persistent() -> Self {
+        Self {
+            shell: Some(Mutex::new(None)),
+            jobs: None,
+        }
+    }
+
+    pub fn with_background_jobs(mut self, jobs: Arc<BackgroundJobs>) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    fn run_in_background(&self, command: &str, context: &ToolContext) -> Result<ToolResult> {
+        let Some(jobs) = &self.jobs else {
+            return Ok(ToolResult::error(
+                "Background jobs are not available here".to_string(),
+            ));
+        };
+
+        // Start where the persistent shell currently is, if there is one
+        let mut context = context.clone();
+        if let Some(cwd) = self.shell.as_ref().and_then(|shell| {
+            let mut shell = shell.lock().unwrap();
+            shell.as_mut().and_then(|session| session.cwd())
+        }) {
+            context.cwd = cwd;
+        }
+
+        let handle = jobs.start(command, &context)?;
+        Ok(ToolResult::success(format!(
+            "Started background job {}. Use bash_output to read its output, \
+             bash_status to check whether it is still running and bash_kill to stop it.",
+            handle
+        )))
+    }
+
+    fn run_persistent(
+        shell: &Mutex<Option<ShellSession>>,
+        command: &str,
+        timeout: Duration,
+        context: &ToolContext,
+    ) -> Result<(String, String, Outcome)> {
+        let mut shell = shell.lock().unwrap();
+        if !shell.as_mut().is_some_and(|session| session.is_alive()) {
+            *shell = Some(ShellSession::spawn(context)?);
+        }
+
+        let session = shell.as_mut().unwrap();
+        let output = session.run(command, timeout, &context.cancel)?;
+        if !matches!(output.outcome, Outcome::Exited(_)) {
+            // Dropping the session kills what is left of it; the next
+            // command starts a new shell
+            *shell = None;
+        }
+        Ok((output.stdout, output.stderr, output.outcome))
+    }
+}
+
 impl Tool for BashTool {
     fn name(&self) -> &str {
         "bash"
     }
 
     fn description(&self) -> &str {
-        "Executes bash commands with optional timeout"
+        if self.shell.is_some() {
+            "Executes bash commands with optional timeout. Use this to run shell commands, git \
+             operations, build tools, etc. By default commands share a persistent shell, so the \
+             working directory, exported variables and shell functions carry over between calls."
+        } else {
+            "Executes bash commands with optional timeout. Use this to run shell commands, git \
+             operations, build tools, etc."
+        }
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<BashParams>()
     }
 
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let bash_params: BashParams = serde_json::from_value(params.data)
             .context("Failed to parse bash parameters")?;
 
-        if let Some(ref desc) = bash_params.description {
-            println!("Executing: {}", desc);
+        if bash_params.run_in_background {
+            return self.run_in_background(&bash_params.command, context);
         }
 
-        let output = Command::new("bash")
-            .arg("-c")
-            .arg(&bash_params.command)
-            .output()
-            .context("Failed to execute bash command")?;
+        let max_timeout_ms = context.limits.max_timeout.as_millis() as u64;
+        let timeout_ms = bash_params.timeout.min(max_timeout_ms);
+        let timeout = Duration::from_millis(timeout_ms);
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let (stdout, stderr, outcome) = match &self.shell {
+            Some(shell) if !bash_params.fresh => {
+                Self::run_persistent(shell, &bash_params.command, timeout, context)?
+            }
+            _ => run_fresh(&bash_params.command, timeout, context)?,
+        };
 
         let combined_output = if !stdout.is_empty() && !stderr.is_empty() {
             format!("STDOUT:\n{}\n\nSTDERR:\n{}", stdout, stderr)
@@ -54,14 +188,287 @@ impl Tool for BashTool {
             stderr
         };
 
-        if output.status.success() {
-            Ok(ToolResult::success(combined_output))
-        } else {
-            Ok(ToolResult::error(format!(
-                "Command failed with exit code: {:?}\n{}",
-                output.status.code(),
-                combined_output
-            )))
+        let mut message = match outcome {
+            Outcome::Exited(Some(0)) => return Ok(ToolResult::success(combined_output)),
+            Outcome::Exited(code) => {
+                return Ok(ToolResult::error(format!(
+                    "Command failed with exit code: {:?}\n{}",
+                    code, combined_output
+                )))
+            }
+            Outcome::TimedOut => {
+                let mut message =
+                    format!("Command timed out after {} ms and was killed", timeout_ms);
+                if bash_params.timeout > max_timeout_ms {
+                    message.push_str(&format!(
+                        " (requested timeout of {} ms exceeds the maximum)",
+                        bash_params.timeout
+                    ));
+                }
+                message
+            }
+            Outcome::Cancelled => "Command was cancelled and killed".to_string(),
+            Outcome::ShellExited(code) => format!("The shell exited with code {:?}", code),
+        };
+        if self.shell.is_some() && !bash_params.fresh {
+            message.push_str(
+                ". The persistent shell was restarted, so its working directory and \
+                 environment were reset",
+            );
+        }
+        if !combined_output.is_empty() {
+            message.push_str("\nPartial output:\n");
+            message.push_str(&combined_output);
+        }
+        Ok(ToolResult::error(message))
+    }
+}
+
+/// A `bash` process started in the context's working directory, in its own
+/// process group, with the session id exported as `CODE_AGENT_SESSION_ID`
+pub(super) fn bash_command(context: &ToolContext) -> Command {
+    let mut process = Command::new("bash");
+    // A new process group lets a timeout or cancellation also reach
+    // grandchildren (e.g. the test binaries `cargo test` spawns)
+    process
+        .current_dir(&context.cwd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    if let Some(session_id) = &context.session_id {
+        process.env("CODE_AGENT_SESSION_ID", session_id);
+    }
+    process
+}
+
+/// Run a command in its own `bash -c`
+fn run_fresh(
+    command: &str,
+    timeout: Duration,
+    context: &ToolContext,
+) -> Result<(String, String, Outcome)> {
+    let mut child = bash_command(context)
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .spawn()
+        .context("Failed to execute bash command")?;
+
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+
+    let outcome = wait_with_deadline(&mut child, deadline, &context.cancel, [&stdout, &stderr])?;
+
+    Ok((
+        String::from_utf8_lossy(&stdout.finish()).to_string(),
+        String::from_utf8_lossy(&stderr.finish()).to_string(),
+        outcome,
+    ))
+}
+
+/// Output of one pipe, collected on a background thread so it is
+/// available even if the command has to be killed
+pub(super) struct Capture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// The pipe reached EOF, i.e. no process holds it open any more
+    pub(super) fn is_closed(&self) -> bool {
+        self.reader.as_ref().is_none_or(|reader| reader.is_finished())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if let Some(reader) = self.reader.take() {
+            if reader.is_finished() {
+                let _ = reader.join();
+            }
+        }
+        self.take()
+    }
+
+    /// Remove and return everything read so far
+    pub(super) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buffer.lock().unwrap())
+    }
+
+    /// Position of `needle` in what was read so far
+    pub(super) fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buffer
+            .lock()
+            .unwrap()
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+}
+
+pub(super) fn capture<R: Read + Send + 'static>(pipe: Option<R>) -> Capture {
+    capture_into(pipe, Arc::new(Mutex::new(Vec::new())))
+}
+
+/// Like `capture`, but appending to a buffer shared with other pipes
+pub(super) fn capture_into<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    buffer: Arc<Mutex<Vec<u8>>>,
+) -> Capture {
+    let reader = pipe.map(|mut pipe| {
+        let buffer = Arc::clone(&buffer);
+        thread::spawn(move || {
+            let mut chunk = [0u8; 8192];
+            while let Ok(n) = pipe.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                buffer.lock().unwrap().extend_from_slice(&chunk[..n]);
+            }
+        })
+    });
+    Capture { buffer, reader }
+}
+
+/// Wait for the command and for its output pipes to close. The process group
+/// is killed if the deadline passes or `cancel` fires before the command
+/// exits.
+fn wait_with_deadline(
+    child: &mut Child,
+    deadline: Instant,
+    cancel: &CancellationToken,
+    pipes: [&Capture; 2],
+) -> Result<Outcome> {
+    let mut exited = None;
+    loop {
+        if exited.is_none() {
+            exited = child
+                .try_wait()
+                .context("Failed to wait for bash command")?
+                .map(|status| (status, Instant::now()));
+        }
+        if let Some((status, at)) = exited {
+            // Processes the command left running in the background (`server &`)
+            // can keep the pipes open indefinitely; keep what they wrote right
+            // away and leave them running
+            if pipes.iter().all(|pipe| pipe.is_closed()) || at.elapsed() >= EXIT_DRAIN {
+                return Ok(Outcome::Exited(status.code()));
+            }
+        } else if cancel.is_cancelled() || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    kill_group(child, false);
+    child.wait().context("Failed to wait for bash command")?;
+    // Give the readers a moment to drain what was written before the kill
+    let drained = Instant::now() + Duration::from_millis(100);
+    while Instant::now() < drained && !pipes.iter().all(|pipe| pipe.is_closed()) {
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(if cancel.is_cancelled() {
+        Outcome::Cancelled
+    } else {
+        Outcome::TimedOut
+    })
+}
+
+/// Send SIGTERM to the command's process group, then SIGKILL to whatever
+/// is still alive after a grace period
+pub(super) fn kill_group(child: &mut Child, exited: bool) {
+    let pgid = child.id() as libc::pid_t;
+    // SAFETY: kill(2) with a negative pid only signals the process group
+    // created for this command
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+
+    let mut exited = exited;
+    let grace = Instant::now() + KILL_GRACE;
+    while Instant::now() < grace {
+        if !exited {
+            exited = matches!(child.try_wait(), Ok(Some(_)));
         }
+        // Once the leader is reaped, signal 0 tells whether any member is left
+        if exited && unsafe { libc::kill(-pgid, 0) } != 0 {
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &str, timeout_ms: u64) -> (String, Outcome, Duration) {
+        let start = Instant::now();
+        let (stdout, _, outcome) =
+            run_fresh(command, Duration::from_millis(timeout_ms), &ToolContext::default()).unwrap();
+        (stdout, outcome, start.elapsed())
+    }
+
+    fn is_running(pid: &str) -> bool {
+        // Orphans are not always reaped promptly in containers; a zombie
+        // counts as gone
+        Command::new("ps")
+            .args(["-o", "stat=", "-p", pid.trim()])
+            .output()
+            .is_ok_and(|out| out.status.success() && !out.stdout.starts_with(b"Z"))
+    }
+
+    #[test]
+    fn commands_are_killed_at_the_deadline() {
+        let (stdout, outcome, elapsed) = run("echo before; sleep 10; echo after", 200);
+        assert!(matches!(outcome, Outcome::TimedOut));
+        assert_eq!(stdout, "before\n");
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timeouts_kill_the_whole_process_group() {
+        let (stdout, outcome, _) = run("sleep 30 & echo $!; wait", 200);
+        assert!(matches!(outcome, Outcome::TimedOut));
+        assert!(!stdout.trim().is_empty());
+        assert!(!is_running(&stdout));
+    }
+
+    #[test]
+    fn background_children_do_not_hold_up_the_exit_status() {
+        let (stdout, outcome, elapsed) = run("sleep 30 & echo $!; exit 3", 10_000);
+        assert!(matches!(outcome, Outcome::Exited(Some(3))));
+        assert!(elapsed < Duration::from_secs(5));
+
+        // The background child is left alone
+        assert!(is_running(&stdout));
+        let pid: libc::pid_t = stdout.trim().parse().unwrap();
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+
+    #[test]
+    fn persistent_shell_restarts_after_exit() {
+        let tool = BashTool::persistent();
+        let bash = |command: &str| {
+            tool.execute(ToolParams {
+                data: serde_json::json!({ "command": command }),
+            })
+            .unwrap()
+        };
+
+        assert!(bash("export GREETING=hello").success);
+        assert_eq!(bash("echo $GREETING").output, "hello\n");
+
+        let result = bash("exit 3");
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("shell exited with code Some(3)"));
+
+        let result = bash("echo \"[$GREETING]\"");
+        assert!(result.success);
+        assert_eq!(result.output, "[]\n");
     }
 }
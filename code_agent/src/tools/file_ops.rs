@@ -1,19 +1,23 @@
-use super::{Tool, ToolParams, ToolResult};
+use super::{parameters_schema, Tool, ToolContext, ToolParams, ToolResult};
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fs;
-use std::path::Path;
 
 /// Tool for reading files
 pub struct ReadTool;
 
-#[derive(Debug, Deserialize)]
-struct ReadParams {
-    file_path: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReadParams {
+    /// The absolute or relative path to the file to read
+    pub file_path: String,
+    /// Optional line number to start reading from (0-indexed)
     #[serde(default)]
-    offset: Option<usize>,
+    pub offset: Option<usize>,
+    /// Optional number of lines to read
     #[serde(default)]
-    limit: Option<usize>,
+    pub limit: Option<usize>,
 }
 
 impl Tool for ReadTool {
@@ -25,11 +29,23 @@ impl Tool for ReadTool {
         "Reads a file from the filesystem with optional line offset and limit"
     }
 
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<ReadParams>()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let read_params: ReadParams = serde_json::from_value(params.data)
             .context("Failed to parse read parameters")?;
 
-        let content = fs::read_to_string(&read_params.file_path)
+        let content = fs::read_to_string(context.resolve(&read_params.file_path))
             .context(format!("Failed to read file: {}", read_params.file_path))?;
 
         let lines: Vec<&str> = content.lines().collect();
@@ -51,10 +67,12 @@ impl Tool for ReadTool {
 /// Tool for writing files
 pub struct WriteTool;
 
-#[derive(Debug, Deserialize)]
-struct WriteParams {
-    file_path: String,
-    content: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WriteParams {
+    /// The path to the file to write
+    pub file_path: String,
+    /// The content to write to the file
+    pub content: String,
 }
 
 impl Tool for WriteTool {
@@ -66,17 +84,26 @@ impl Tool for WriteTool {
         "Writes content to a file, creating or overwriting as needed"
     }
 
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<WriteParams>()
+    }
+
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let write_params: WriteParams = serde_json::from_value(params.data)
             .context("Failed to parse write parameters")?;
+        let path = context.resolve(&write_params.file_path);
 
         // Create parent directories if they don't exist
-        if let Some(parent) = Path::new(&write_params.file_path).parent() {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)
                 .context(format!("Failed to create parent directories for: {}", write_params.file_path))?;
         }
 
-        fs::write(&write_params.file_path, &write_params.content)
+        fs::write(&path, &write_params.content)
             .context(format!("Failed to write file: {}", write_params.file_path))?;
 
         Ok(ToolResult::success(format!(
@@ -90,13 +117,17 @@ impl Tool for WriteTool {
 /// Tool for editing files via string replacement
 pub struct EditTool;
 
-#[derive(Debug, Deserialize)]
-struct EditParams {
-    file_path: String,
-    old_string: String,
-    new_string: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EditParams {
+    /// The path to the file to edit
+    pub file_path: String,
+    /// The exact string to find and replace
+    pub old_string: String,
+    /// The replacement string
+    pub new_string: String,
+    /// If true, replace all occurrences. Default is false.
     #[serde(default)]
-    replace_all: bool,
+    pub replace_all: bool,
 }
 
 impl Tool for EditTool {
@@ -105,14 +136,23 @@ impl Tool for EditTool {
     }
 
     fn description(&self) -> &str {
-        "Performs exact string replacements in files"
+        "Performs exact string replacements in files. The old_string must be unique unless replace_all is true."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<EditParams>()
     }
 
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let edit_params: EditParams = serde_json::from_value(params.data)
             .context("Failed to parse edit parameters")?;
+        let path = context.resolve(&edit_params.file_path);
 
-        let content = fs::read_to_string(&edit_params.file_path)
+        let content = fs::read_to_string(&path)
             .context(format!("Failed to read file: {}", edit_params.file_path))?;
 
         let new_content = if edit_params.replace_all {
@@ -134,7 +174,7 @@ impl Tool for EditTool {
             content.replacen(&edit_params.old_string, &edit_params.new_string, 1)
         };
 
-        fs::write(&edit_params.file_path, &new_content)
+        fs::write(&path, &new_content)
             .context(format!("Failed to write file: {}", edit_params.file_path))?;
 
         Ok(ToolResult::success(format!(
@@ -1,21 +1,212 @@
 pub mod file_ops;
 pub mod search;
 pub mod bash;
+pub mod background;
+pub mod shell;
 pub mod todo;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use schemars::generate::SchemaSettings;
+use schemars::transform::RecursiveTransform;
+use schemars::{JsonSchema, Schema};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
-/// Trait that all tools must implement
-pub trait Tool {
+/// How long a cancelled blocking tool gets to stop on its own and report
+/// partial output before the call is abandoned. Covers bash's SIGTERM grace
+/// period.
+const CANCEL_GRACE: Duration = Duration::from_secs(3);
+
+/// Trait for tools that do their work synchronously. The agent runs them
+/// through the `Blocking` adapter so they never block the async runtime.
+pub trait Tool: Send + Sync {
     /// Get the name of the tool
     fn name(&self) -> &str;
 
     /// Get a description of what the tool does
     fn description(&self) -> &str;
 
+    /// JSON schema of the parameters, as advertised to the model.
+    /// Generate it from the params struct with `parameters_schema::<T>()`.
+    fn parameters_schema(&self) -> Value;
+
+    /// Whether the tool only reads state. Read-only calls made in the same
+    /// assistant turn may run concurrently; all others run one at a time.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
     /// Execute the tool with given parameters
     fn execute(&self, params: ToolParams) -> Result<ToolResult>;
+
+    /// Execute the tool within `context`. Long-running tools override this
+    /// to stop early when the context is cancelled; by default the context
+    /// is ignored.
+    fn execute_in(&self, params: ToolParams, _context: &ToolContext) -> Result<ToolResult> {
+        self.execute(params)
+    }
+}
+
+/// Trait for tools the agent can call. Execution is async and receives the
+/// context of the call, including the token that cancels it.
+#[async_trait]
+pub trait AsyncTool: Send + Sync {
+    /// Get the name of the tool
+    fn name(&self) -> &str;
+
+    /// Get a description of what the tool does
+    fn description(&self) -> &str;
+
+    /// JSON schema of the parameters, as advertised to the model
+    fn parameters_schema(&self) -> Value;
+
+    /// Whether the tool only reads state; see `Tool::is_read_only`
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Execute the tool with given parameters
+    async fn execute(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult>;
+}
+
+/// Runs a synchronous `Tool` on tokio's blocking thread pool
+pub struct Blocking<T>(Arc<T>);
+
+impl<T> Blocking<T> {
+    pub fn new(tool: T) -> Self {
+        Self(Arc::new(tool))
+    }
+}
+
+#[async_trait]
+impl<T: Tool + 'static> AsyncTool for Blocking<T> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    fn parameters_schema(&self) -> Value {
+        self.0.parameters_schema()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.0.is_read_only()
+    }
+
+    async fn execute(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
+        let tool = Arc::clone(&self.0);
+        let cancel = context.cancel.clone();
+        let context = context.clone();
+        let mut task = tokio::task::spawn_blocking(move || tool.execute_in(params, &context));
+
+        let joined = tokio::select! {
+            joined = &mut task => joined,
+            _ = cancel.cancelled() => {
+                // Tools watching the token stop by themselves; anything else
+                // is left to finish in the background
+                match tokio::time::timeout(CANCEL_GRACE, &mut task).await {
+                    Ok(joined) => joined,
+                    Err(_) => {
+                        return Ok(ToolResult::error(format!("{} was cancelled", self.name())))
+                    }
+                }
+            }
+        };
+        joined.with_context(|| format!("{} panicked", self.name()))?
+    }
+}
+
+/// Where and within which limits a tool call runs
+#[derive(Debug, Clone)]
+pub struct ToolContext {
+    /// Directory commands start in and relative paths are resolved against
+    pub cwd: PathBuf,
+    /// Session the call belongs to, if the conversation is being recorded
+    pub session_id: Option<String>,
+    pub limits: ToolLimits,
+    /// Cancelled when the user interrupts the call
+    pub cancel: CancellationToken,
+}
+
+impl Default for ToolContext {
+    fn default() -> Self {
+        Self {
+            cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            session_id: None,
+            limits: ToolLimits::default(),
+            cancel: CancellationToken::new(),
+        }
+    }
+}
+
+impl ToolContext {
+    /// `path` resolved against the working directory, unless it is absolute
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.cwd.join(path)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ToolLimits {
+    /// Longest a bash command may run in the foreground
+    pub max_timeout: Duration,
+    /// Output beyond this many bytes is cut before it reaches the model
+    pub max_output_bytes: usize,
+}
+
+impl Default for ToolLimits {
+    fn default() -> Self {
+        Self {
+            max_timeout: Duration::from_millis(bash::MAX_TIMEOUT_MS),
+            max_output_bytes: 100_000,
+        }
+    }
+}
+
+/// JSON schema for a tool's params type, with subschemas inlined so it can
+/// be sent as-is as a function definition
+pub fn parameters_schema<T: JsonSchema>() -> Value {
+    let mut settings = SchemaSettings::draft07();
+    settings.inline_subschemas = true;
+    settings.meta_schema = None;
+    settings.transforms.push(Box::new(RecursiveTransform(simplify_schema)));
+
+    let schema = settings.into_generator().into_root_schema_for::<T>();
+    let mut value = schema.to_value();
+    if let Some(object) = value.as_object_mut() {
+        object.remove("title");
+    }
+    value
+}
+
+/// Optional fields would otherwise be advertised as nullable (`"type":
+/// ["string", "null"]`, `"default": null`), and integers carry Rust-specific
+/// formats like `uint`; none of that tells the model anything
+fn simplify_schema(schema: &mut Schema) {
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("format");
+        if object.get("default") == Some(&Value::Null) {
+            object.remove("default");
+        }
+        if let Some(Value::Array(options)) = object.get_mut("enum") {
+            options.retain(|option| !option.is_null());
+        }
+        if let Some(Value::Array(types)) = object.get_mut("type") {
+            types.retain(|t| t != "null");
+            if types.len() == 1 {
+                let single = types.remove(0);
+                object.insert("type".to_string(), single);
+            }
+        }
+    }
 }
 
 /// Parameters passed to a tool
@@ -50,4 +241,19 @@ impl ToolResult {
             error: Some(error),
         }
     }
+
+    /// Cut the output and error message to at most `max_bytes` each
+    pub fn truncate(&mut self, max_bytes: usize) {
+        for text in std::iter::once(&mut self.output).chain(self.error.as_mut()) {
+            if text.len() > max_bytes {
+                let omitted = text.len() - max_bytes;
+                let mut end = max_bytes;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text.truncate(end);
+                text.push_str(&format!("\n[... {} more bytes truncated]", omitted));
+            }
+        }
+    }
 }
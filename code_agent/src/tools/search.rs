@@ -1,20 +1,24 @@
-use super::{Tool, ToolParams, ToolResult};
+use super::{parameters_schema, Tool, ToolContext, ToolParams, ToolResult};
 use anyhow::{Context, Result};
 use glob::glob;
 use regex::Regex;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
 /// Tool for finding files using glob patterns
 pub struct GlobTool;
 
-#[derive(Debug, Deserialize)]
-struct GlobParams {
-    pattern: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GlobParams {
+    /// The glob pattern to match files against
+    pub pattern: String,
+    /// Optional directory to search in. Defaults to current directory.
     #[serde(default)]
-    path: Option<String>,
+    pub path: Option<String>,
 }
 
 impl Tool for GlobTool {
@@ -23,15 +27,34 @@ impl Tool for GlobTool {
     }
 
     fn description(&self) -> &str {
-        "Fast file pattern matching using glob patterns like **/*.js"
+        "Fast file pattern matching using glob patterns like **/*.js or src/**/*.rs"
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<GlobParams>()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
     }
 
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let glob_params: GlobParams = serde_json::from_value(params.data)
             .context("Failed to parse glob parameters")?;
 
         let search_path = glob_params.path.unwrap_or_else(|| ".".to_string());
-        let full_pattern = format!("{}/{}", search_path, glob_params.pattern);
+        let base = display_base(&search_path, context);
+        // The working directory is taken literally, not as a pattern
+        let full_pattern = if base.as_os_str().is_empty() {
+            format!("{}/{}", search_path, glob_params.pattern)
+        } else {
+            let cwd = glob::Pattern::escape(&base.to_string_lossy());
+            format!("{}/{}/{}", cwd, search_path, glob_params.pattern)
+        };
 
         let mut matches: Vec<PathBuf> = glob(&full_pattern)
             .context("Failed to parse glob pattern")?
@@ -43,7 +66,7 @@ impl Tool for GlobTool {
             fs::metadata(path)
                 .and_then(|m| m.modified())
                 .ok()
-                .map(|t| std::cmp::Reverse(t))
+                .map(std::cmp::Reverse)
         });
 
         let output = if matches.is_empty() {
@@ -54,7 +77,7 @@ impl Tool for GlobTool {
                 matches.len(),
                 matches
                     .iter()
-                    .map(|p| p.display().to_string())
+                    .map(|p| shown(p, &base))
                     .collect::<Vec<_>>()
                     .join("\n")
             )
@@ -67,25 +90,38 @@ impl Tool for GlobTool {
 /// Tool for searching file contents using regex
 pub struct GrepTool;
 
-#[derive(Debug, Deserialize)]
-struct GrepParams {
-    pattern: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GrepParams {
+    /// The regex pattern to search for
+    pub pattern: String,
+    /// Optional directory to search in. Defaults to current directory.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Optional glob pattern to filter files (e.g., '*.rs', '*.txt')
     #[serde(default)]
-    path: Option<String>,
+    pub glob: Option<String>,
+    /// If true, search is case-insensitive. Default is false.
     #[serde(default)]
-    glob: Option<String>,
+    pub case_insensitive: bool,
+    /// Output format: 'files_with_matches' (just filenames), 'content'
+    /// (matching lines), or 'count' (match counts)
     #[serde(default)]
-    case_insensitive: bool,
-    #[serde(default = "default_output_mode")]
-    output_mode: String,
+    pub output_mode: OutputMode,
+    /// Number of lines to show before each match in 'content' mode
     #[serde(default)]
-    context_before: usize,
+    pub context_before: usize,
+    /// Number of lines to show after each match in 'content' mode
     #[serde(default)]
-    context_after: usize,
+    pub context_after: usize,
 }
 
-fn default_output_mode() -> String {
-    "files_with_matches".to_string()
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    #[default]
+    FilesWithMatches,
+    Content,
+    Count,
 }
 
 impl Tool for GrepTool {
@@ -94,14 +130,27 @@ impl Tool for GrepTool {
     }
 
     fn description(&self) -> &str {
-        "Search tool for finding patterns in file contents using regex"
+        "Search file contents using regex patterns. Supports filtering by file type and multiple output modes."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<GrepParams>()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
     }
 
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let grep_params: GrepParams = serde_json::from_value(params.data)
             .context("Failed to parse grep parameters")?;
 
         let search_path = grep_params.path.unwrap_or_else(|| ".".to_string());
+        let base = display_base(&search_path, context);
 
         let regex = if grep_params.case_insensitive {
             Regex::new(&format!("(?i){}", grep_params.pattern))
@@ -113,12 +162,16 @@ impl Tool for GrepTool {
         let mut results = Vec::new();
 
         // Walk through directory
-        for entry in WalkDir::new(&search_path)
+        for entry in WalkDir::new(context.resolve(&search_path))
             .follow_links(false)
             .into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
         {
+            if context.cancel.is_cancelled() {
+                results.push("[Search cancelled; results are incomplete]".to_string());
+                return Ok(ToolResult::error(results.join("\n")));
+            }
             let path = entry.path();
 
             // Apply glob filter if specified
@@ -135,29 +188,30 @@ impl Tool for GrepTool {
             // Try to read file as text
             if let Ok(content) = fs::read_to_string(path) {
                 let lines: Vec<&str> = content.lines().collect();
-                let mut matching_lines = Vec::new();
-
-                for (line_num, line) in lines.iter().enumerate() {
-                    if regex.is_match(line) {
-                        matching_lines.push((line_num, line));
-                    }
-                }
+                let matching_lines: Vec<usize> = lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, line)| regex.is_match(line))
+                    .map(|(line_num, _)| line_num)
+                    .collect();
 
                 if !matching_lines.is_empty() {
-                    match grep_params.output_mode.as_str() {
-                        "files_with_matches" => {
-                            results.push(path.display().to_string());
+                    match grep_params.output_mode {
+                        OutputMode::FilesWithMatches => {
+                            results.push(shown(path, &base));
                         }
-                        "content" => {
-                            results.push(format!("\n{}:", path.display()));
-                            for (line_num, line) in matching_lines {
-                                results.push(format!("{:5}: {}", line_num + 1, line));
-                            }
+                        OutputMode::Content => {
+                            results.push(format!("\n{}:", shown(path, &base)));
+                            results.extend(format_matches(
+                                &lines,
+                                &matching_lines,
+                                grep_params.context_before,
+                                grep_params.context_after,
+                            ));
                         }
-                        "count" => {
-                            results.push(format!("{}: {}", path.display(), matching_lines.len()));
+                        OutputMode::Count => {
+                            results.push(format!("{}: {}", shown(path, &base), matching_lines.len()));
                         }
-                        _ => {}
                     }
                 }
             }
@@ -172,3 +226,48 @@ impl Tool for GrepTool {
         Ok(ToolResult::success(output))
     }
 }
+
+/// Prefix to strip from found paths so they are shown the way the search
+/// path was given: relative to the working directory unless it is absolute
+fn display_base(search_path: &str, context: &ToolContext) -> PathBuf {
+    if Path::new(search_path).is_absolute() {
+        PathBuf::new()
+    } else {
+        context.cwd.clone()
+    }
+}
+
+fn shown(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base).unwrap_or(path).display().to_string()
+}
+
+/// Matching lines (`  12: ...`) with their context lines (`  11- ...`);
+/// non-adjacent groups are separated by `--` like grep does
+fn format_matches(lines: &[&str], matches: &[usize], before: usize, after: usize) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut next_line = 0;
+
+    for (i, &line_num) in matches.iter().enumerate() {
+        let start = line_num.saturating_sub(before).max(next_line);
+        if i > 0 && start > next_line && (before > 0 || after > 0) {
+            output.push("--".to_string());
+        }
+        for (ctx_num, line) in lines.iter().enumerate().take(line_num).skip(start) {
+            output.push(format!("{:5}- {}", ctx_num + 1, line));
+        }
+        output.push(format!("{:5}: {}", line_num + 1, lines[line_num]));
+
+        // Context after stops at the next match, which prints itself
+        let end = matches
+            .get(i + 1)
+            .copied()
+            .unwrap_or(lines.len())
+            .min(line_num + 1 + after);
+        for (ctx_num, line) in lines.iter().enumerate().take(end).skip(line_num + 1) {
+            output.push(format!("{:5}- {}", ctx_num + 1, line));
+        }
+        next_line = end;
+    }
+
+    output
+}
@@ -0,0 +1,216 @@
+use super::bash::{bash_command, capture, kill_group, Capture, Outcome, POLL_INTERVAL};
+use super::ToolContext;
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// A long-lived `bash` process whose working directory, environment and
+/// shell functions carry over from one command to the next
+pub struct ShellSession {
+    /// Only taken when the session is dropped
+    child: Option<Child>,
+    stdin: ChildStdin,
+    stdout: Capture,
+    stderr: Capture,
+    /// Marker printed after each command to find the end of its output
+    sentinel: String,
+}
+
+/// Output of one command run in the session
+pub struct ShellOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub outcome: Outcome,
+}
+
+impl ShellSession {
+    /// Start a shell in the context's working directory
+    pub fn spawn(context: &ToolContext) -> Result<Self> {
+        let mut child = bash_command(context)
+            .arg("--noprofile")
+            .arg("--norc")
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed to start persistent bash shell")?;
+
+        let stdin = child.stdin.take().context("Shell has no stdin")?;
+        let stdout = capture(child.stdout.take());
+        let stderr = capture(child.stderr.take());
+
+        Ok(Self {
+            child: Some(child),
+            stdin,
+            stdout,
+            stderr,
+            sentinel: format!("__CODE_AGENT_DONE_{:016x}__", rand::thread_rng().gen::<u64>()),
+        })
+    }
+
+    fn child(&mut self) -> &mut Child {
+        self.child.as_mut().expect("shell process is only taken on drop")
+    }
+
+    /// Whether the shell process is still running
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child().try_wait(), Ok(None))
+    }
+
+    /// Current working directory of the shell, asked through `pwd`
+    pub fn cwd(&mut self) -> Option<PathBuf> {
+        if !self.is_alive() {
+            return None;
+        }
+        let output = self
+            .run("pwd", Duration::from_secs(5), &CancellationToken::new())
+            .ok()?;
+        match output.outcome {
+            Outcome::Exited(Some(0)) => Some(PathBuf::from(output.stdout.trim_end_matches('\n'))),
+            _ => None,
+        }
+    }
+
+    /// Run a command and wait until it finishes, the shell dies, the
+    /// deadline passes or `cancel` fires. After any outcome other than
+    /// `Outcome::Exited` the session is dead and must be replaced.
+    pub fn run(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<ShellOutput> {
+        // Output written by background jobs between commands is dropped
+        self.stdout.take();
+        self.stderr.take();
+
+        // The command goes through `eval` so a syntax error fails only this
+        // command, and reads /dev/null so it cannot consume the next script
+        let script = format!(
+            "eval '{}' < /dev/null\n\
+             __code_agent_status=$?\n\
+             printf '\\n%s %d\\n' '{sentinel}' \"$__code_agent_status\"\n\
+             printf '\\n%s\\n' '{sentinel}' >&2\n",
+            command.replace('\'', "'\\''"),
+            sentinel = self.sentinel
+        );
+        self.stdin
+            .write_all(script.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .context("Failed to send command to persistent shell")?;
+
+        let stdout_marker = format!("\n{} ", self.sentinel);
+        let stderr_marker = format!("\n{}\n", self.sentinel);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let stdout_end = self.stdout.find(stdout_marker.as_bytes());
+            let stderr_end = self.stderr.find(stderr_marker.as_bytes());
+            if let (Some(stdout_end), Some(stderr_end)) = (stdout_end, stderr_end) {
+                let stdout = self.stdout.take();
+                let stderr = self.stderr.take();
+                let status = String::from_utf8_lossy(&stdout[stdout_end + stdout_marker.len()..])
+                    .trim()
+                    .parse()
+                    .ok();
+                return Ok(ShellOutput {
+                    stdout: String::from_utf8_lossy(&stdout[..stdout_end]).to_string(),
+                    stderr: String::from_utf8_lossy(&stderr[..stderr_end]).to_string(),
+                    outcome: Outcome::Exited(status),
+                });
+            }
+
+            if let Some(status) = self.child().try_wait().context("Failed to wait for shell")? {
+                // e.g. `exit` or `set -e`; collect what it wrote on the way out
+                let drained = Instant::now() + Duration::from_millis(100);
+                while Instant::now() < drained && !(self.stdout.is_closed() && self.stderr.is_closed()) {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                return Ok(self.output(Outcome::ShellExited(status.code())));
+            }
+
+            if cancel.is_cancelled() {
+                kill_group(self.child(), false);
+                return Ok(self.output(Outcome::Cancelled));
+            }
+            if Instant::now() >= deadline {
+                kill_group(self.child(), false);
+                return Ok(self.output(Outcome::TimedOut));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn output(&mut self, outcome: Outcome) -> ShellOutput {
+        ShellOutput {
+            stdout: String::from_utf8_lossy(&self.stdout.take()).to_string(),
+            stderr: String::from_utf8_lossy(&self.stderr.take()).to_string(),
+            outcome,
+        }
+    }
+}
+
+impl Drop for ShellSession {
+    /// Take down the shell and anything it left running in the background
+    fn drop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        let exited = matches!(child.try_wait(), Ok(Some(_)));
+        // The grace period before SIGKILL must not block the caller, which
+        // may be an async task
+        thread::spawn(move || {
+            kill_group(&mut child, exited);
+            let _ = child.wait();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut ShellSession, command: &str) -> ShellOutput {
+        session
+            .run(command, Duration::from_secs(10), &CancellationToken::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn output_is_framed_per_command() {
+        let mut session = ShellSession::spawn(&ToolContext::default()).unwrap();
+        let output = run(&mut session, "echo out; echo err >&2; printf partial; exit_code() { return 4; }; exit_code");
+        assert_eq!(output.stdout, "out\npartial");
+        assert_eq!(output.stderr, "err\n");
+        assert!(matches!(output.outcome, Outcome::Exited(Some(4))));
+
+        // Text that looks like a marker is plain output
+        let output = run(&mut session, "echo '__CODE_AGENT_DONE_0123456789abcdef__ 0'; echo \"it's\" done");
+        assert_eq!(output.stdout, "__CODE_AGENT_DONE_0123456789abcdef__ 0\nit's done\n");
+        assert!(matches!(output.outcome, Outcome::Exited(Some(0))));
+    }
+
+    #[test]
+    fn directory_and_environment_carry_over() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let mut session = ShellSession::spawn(&ToolContext::default()).unwrap();
+        run(&mut session, &format!("cd '{}' && export GREETING=hello", dir.display()));
+        assert_eq!(session.cwd(), Some(dir.clone()));
+
+        let output = run(&mut session, "echo \"$GREETING from $(pwd)\"");
+        assert_eq!(output.stdout, format!("hello from {}\n", dir.display()));
+    }
+
+    #[test]
+    fn exit_ends_the_session() {
+        let mut session = ShellSession::spawn(&ToolContext::default()).unwrap();
+        let output = run(&mut session, "echo bye; exit 3");
+        assert_eq!(output.stdout, "bye\n");
+        assert!(matches!(output.outcome, Outcome::ShellExited(Some(3))));
+        assert!(!session.is_alive());
+        assert_eq!(session.cwd(), None);
+    }
+}
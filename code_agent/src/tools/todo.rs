@@ -1,35 +1,49 @@
-use super::{Tool, ToolParams, ToolResult};
+use super::{parameters_schema, Tool, ToolContext, ToolParams, ToolResult};
 use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fs;
-use std::path::PathBuf;
 
 const TODO_FILE: &str = ".code_agent_todos.json";
 
 /// Tool for managing todos
 pub struct TodoTool;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct TodoItem {
+    /// What needs to be done, e.g. "Run tests"
     pub content: String,
     pub status: TodoStatus,
+    /// Present continuous form shown while in progress, e.g. "Running tests"
     pub active_form: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum TodoStatus {
     Pending,
+    // Todo files written before the switch to snake_case say `inprogress`
+    #[serde(alias = "inprogress")]
     InProgress,
     Completed,
 }
 
-#[derive(Debug, Deserialize)]
-struct TodoParams {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoAction {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TodoParams {
+    /// Array of todo items (required for write action)
     #[serde(default)]
-    todos: Option<Vec<TodoItem>>,
+    pub todos: Option<Vec<TodoItem>>,
+    /// Action to perform: 'read' or 'write'
     #[serde(default)]
-    action: Option<String>,
+    pub action: Option<TodoAction>,
 }
 
 impl Tool for TodoTool {
@@ -38,17 +52,25 @@ impl Tool for TodoTool {
     }
 
     fn description(&self) -> &str {
-        "Manages a task list for tracking progress"
+        "Manages a task list for tracking progress. Can read current todos or write new ones."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        parameters_schema::<TodoParams>()
     }
 
     fn execute(&self, params: ToolParams) -> Result<ToolResult> {
+        self.execute_in(params, &ToolContext::default())
+    }
+
+    fn execute_in(&self, params: ToolParams, context: &ToolContext) -> Result<ToolResult> {
         let todo_params: TodoParams = serde_json::from_value(params.data)
             .context("Failed to parse todo parameters")?;
 
-        let todo_path = PathBuf::from(TODO_FILE);
+        let todo_path = context.resolve(TODO_FILE);
 
         // If action is "read" or no todos provided, return current list
-        if todo_params.action.as_deref() == Some("read") || todo_params.todos.is_none() {
+        if todo_params.action == Some(TodoAction::Read) || todo_params.todos.is_none() {
             if todo_path.exists() {
                 let content = fs::read_to_string(&todo_path)?;
                 let todos: Vec<TodoItem> = serde_json::from_str(&content)?;
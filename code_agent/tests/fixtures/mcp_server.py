@@ -0,0 +1,106 @@
+#!/usr/bin/env python3
+"""Minimal MCP server over stdio used by the MCP client tests.
+
+Tools:
+  echo  - returns its `text` argument
+  fail  - returns a result with isError set
+  boom  - answers with a JSON-RPC error
+  slow  - sleeps for `seconds` before answering
+  crash - exits without answering
+"""
+import json
+import os
+import sys
+import threading
+import time
+
+TOOLS = [
+    {
+        "name": "echo",
+        "description": "Echo the given text",
+        "inputSchema": {
+            "type": "object",
+            "properties": {"text": {"type": "string"}},
+            "required": ["text"],
+        },
+        "annotations": {"readOnlyHint": True},
+    },
+    {"name": "fail", "inputSchema": {"type": "object"}},
+    {"name": "boom", "inputSchema": {"type": "object"}},
+    {
+        "name": "slow",
+        "inputSchema": {"type": "object", "properties": {"seconds": {"type": "number"}}},
+    },
+    {"name": "crash", "inputSchema": {}},
+]
+
+lock = threading.Lock()
+
+
+def send(message):
+    with lock:
+        sys.stdout.write(json.dumps(message) + "\n")
+        sys.stdout.flush()
+
+
+def respond(id, result=None, error=None):
+    message = {"jsonrpc": "2.0", "id": id}
+    if error is not None:
+        message["error"] = error
+    else:
+        message["result"] = result
+    send(message)
+
+
+def call_tool(id, name, args):
+    if name == "echo":
+        respond(id, {"content": [{"type": "text", "text": args.get("text", "")}]})
+    elif name == "fail":
+        respond(id, {"content": [{"type": "text", "text": "tracker is down"}], "isError": True})
+    elif name == "boom":
+        respond(id, error={"code": -32602, "message": "bad arguments"})
+    elif name == "slow":
+        time.sleep(args.get("seconds", 10))
+        respond(id, {"content": [{"type": "text", "text": "done"}]})
+    elif name == "crash":
+        print("fatal: crashing on purpose", file=sys.stderr, flush=True)
+        os._exit(3)
+    else:
+        respond(id, error={"code": -32602, "message": "unknown tool " + name})
+
+
+def main():
+    print("fixture server starting", file=sys.stderr, flush=True)
+    for line in sys.stdin:
+        message = json.loads(line)
+        method = message.get("method")
+        id = message.get("id")
+        params = message.get("params") or {}
+
+        if method == "initialize":
+            respond(id, {
+                "protocolVersion": params.get("protocolVersion"),
+                "capabilities": {"tools": {}},
+                "serverInfo": {"name": "fixture", "version": "1.0"},
+            })
+        elif method == "tools/list":
+            # Two pages, with a log notification and a ping in between
+            send({"jsonrpc": "2.0", "method": "notifications/message",
+                  "params": {"level": "info", "data": "listing tools"}})
+            send({"jsonrpc": "2.0", "id": "ping-1", "method": "ping"})
+            if params.get("cursor") == "page-2":
+                respond(id, {"tools": TOOLS[2:]})
+            else:
+                respond(id, {"tools": TOOLS[:2], "nextCursor": "page-2"})
+        elif method == "tools/call":
+            threading.Thread(
+                target=call_tool,
+                args=(id, params["name"], params.get("arguments") or {}),
+                daemon=True,
+            ).start()
+        elif id is not None and method is not None:
+            respond(id, error={"code": -32601, "message": "Method not found"})
+
+
+if __name__ == "__main__":
+    main()